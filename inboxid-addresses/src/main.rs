@@ -0,0 +1,59 @@
+use chrono::{Local, NaiveDateTime, TimeZone};
+use inboxid_lib::*;
+
+const COMPLETION_LIMIT: u32 = 20;
+
+fn main() -> Result<()> {
+	let args = get_args()?;
+	let db = get_db()?;
+	match args.get(1).map(|x| &**x) {
+		Some("vcard") => {
+			for address in all_addresses(&db)? {
+				print!("{}", vcard(&address));
+			}
+		},
+		Some("csv") => {
+			println!("name,address,count,last_seen");
+			for address in all_addresses(&db)? {
+				let last_seen = Local.from_utc_datetime(&NaiveDateTime::from_timestamp(address.last_seen, 0));
+				println!("{},{},{},{}", csv_field(&address.name), csv_field(&address.address), address.count, last_seen.format("%Y-%m-%d"));
+			}
+		},
+		Some("complete") => {
+			let prefix = args.get(2).map(|x| &**x).unwrap_or_default();
+			for address in complete_address(&db, prefix, COMPLETION_LIMIT)? {
+				if address.name.is_empty() {
+					println!("{}", address.address);
+				} else {
+					println!("{} <{}>", address.name, address.address);
+				}
+			}
+		},
+		_ => return Err(InboxidError::Config("usage: inboxid-addresses vcard | csv | complete <prefix>".to_owned()))
+	}
+	Ok(())
+}
+
+/// Format an address as vCard 4.0 (RFC 6350).
+fn vcard(address: &Address) -> String {
+	let name = if address.name.is_empty() {
+		&address.address
+	} else {
+		&address.name
+	};
+	format!("BEGIN:VCARD\r\nVERSION:4.0\r\nFN:{}\r\nEMAIL:{}\r\nEND:VCARD\r\n", vcard_escape(name), vcard_escape(&address.address))
+}
+
+/// Escape a vCard property value (RFC 6350, section 3.4).
+fn vcard_escape(value: &str) -> String {
+	value.replace('\\', "\\\\").replace(',', "\\,").replace(';', "\\;").replace("\r\n", "\\n").replace('\r', "\\n").replace('\n', "\\n")
+}
+
+/// Quote a CSV field if required (RFC 4180).
+fn csv_field(value: &str) -> String {
+	if value.contains(&[',', '"', '\r', '\n'][..]) {
+		format!("\"{}\"", value.replace('"', "\"\""))
+	} else {
+		value.to_owned()
+	}
+}
@@ -1,10 +1,12 @@
 #![feature(internal_output_capture)]
 
-use std::{cell::RefCell, cmp, collections::{HashMap, HashSet}, env, fmt::Display, io, rc::Rc, sync::{Arc, atomic::{AtomicBool, Ordering}}};
+use std::{cell::RefCell, cmp, collections::{HashMap, HashSet, hash_map::Entry}, fmt::Display, io, ops::Deref, rc::Rc, sync::{Arc, atomic::{AtomicBool, Ordering}}};
 use std::result::Result as StdResult;
 
 use cursive::{Cursive, Vec2, WrapMethod, traits::Boxable, view::ViewWrapper, views::{Dialog, EditView}};
 use cursive::align::HAlign;
+use cursive::theme::Style;
+use cursive::utils::span::SpannedString;
 use cursive::event::{Event, Key};
 use cursive::traits::Identifiable;
 use cursive::view::{Scrollable, SizeConstraint, View};
@@ -14,17 +16,18 @@ use inboxid_lib::*;
 use io::Write;
 use itertools::Itertools;
 use log::error;
+use maildir::Maildir;
 use mailparse::{MailHeaderMap, ParsedMail};
 use parking_lot::{Mutex, RwLock};
 use petgraph::{EdgeDirection, graph::{DiGraph, NodeIndex}, visit::{Dfs, IntoNodeReferences}};
-use rusqlite::params;
+use rusqlite::{Connection, Statement, params};
 
 fn main() -> Result<()> {
-	load_config();
+	load_config()?;
 	let sink = Arc::new(std::sync::Mutex::new(Vec::new()));
 	std::io::set_output_capture(Some(sink.clone()));
 	let result = std::panic::catch_unwind(|| {
-		let args = env::args().collect_vec();
+		let args = get_args()?;
 		if args.len() > 1 {
 			show_listing(&args[1])
 		} else {
@@ -37,85 +40,94 @@ fn main() -> Result<()> {
 	match result {
 		Ok(res) => res,
 		Err(_) => {
-			Err("panicked".into()) // not displayed
+			Err(InboxidError::Other("panicked".into())) // not displayed
 		}
 	}
 }
 
 fn show_listing(mailbox: &str) -> Result<()> {
-	let db = Box::leak(Box::new(get_db()?));
-	let update_flags = Arc::new(Mutex::new(db.prepare("UPDATE mail SET flags = ? WHERE uid = ?")?));
+	let db: &'static _ = Box::leak(Box::new(get_db()?));
+	let update_flags = Arc::new(Mutex::new(db.prepare("UPDATE mail SET flags = ? WHERE mailbox = ? AND uid_validity = ? AND uid = ?")?));
+	let mailbox: &'static str = Box::leak(mailbox.to_owned().into_boxed_str());
 	let maildir = Box::leak(Box::new(get_maildir(mailbox)?));
 	let maildir = &*maildir;
 
-	let mut mails = Vec::new();
-	for x in maildir.list_cur() {
-		mails.push(x?);
+	let mut mails = load_summaries(db, mailbox, maildir)?.into_iter().map(EasyMail::from).collect_vec();
+	// threads span mailboxes, also show the mails of other mailboxes (e.g. Sent)
+	let mut thread_ids = HashMap::new();
+	let mut message_ids: HashSet<_> = mails.iter().map(|x| x.message_id.clone()).collect();
+	let mut maildirs = HashMap::new();
+	let mut others = Vec::new();
+	for (thread, other_mailbox, id, message_id) in threads_of_mailbox(db, mailbox)? {
+		if other_mailbox == mailbox {
+			thread_ids.insert(message_id, thread);
+			continue;
+		}
+		let other_maildir = match maildirs.entry(other_mailbox.clone()) {
+			Entry::Occupied(x) => x.into_mut(),
+			Entry::Vacant(x) => x.insert(get_maildir(&other_mailbox)?)
+		};
+		if let Some(summary) = load_summary(db, &other_mailbox, other_maildir, id)? {
+			if message_ids.insert(summary.message_id.clone()) {
+				thread_ids.insert(summary.message_id.clone(), thread);
+				let mut other = EasyMail::from(summary);
+				other.mailbox = Some(other_mailbox);
+				others.push(other);
+			}
+		}
 	}
-	let mails = Box::leak(Box::new(mails.into_iter().map(Box::new).map(Box::leak).collect_vec()));
-	let mut mails = maildir.get_mails2(mails)?;
+	mails.extend(others);
 	mails.sort_by_key(|x| x.date);
-	let mails = Box::leak(Box::new(mails.into_iter().map(Box::new).map(Box::leak).collect_vec()));
+	let mails = mails.into_iter().map(Rc::new).collect_vec();
 
 	let mut mails_by_id = HashMap::new();
-	let mut threads: HashMap<_, Vec<_>> = HashMap::new();
-	for i in 0..mails.len() {
-		let mail = &*mails[i];
-		let mid = mail.get_headers().message_id(mailbox, mail.id);
-		threads.entry(mid.clone()).or_default().push(mail);
-		if mails_by_id.insert(mid, mail).is_some() {
+	// latest mail of every thread
+	let mut thread_dates = HashMap::new();
+	for mail in &mails {
+		if mails_by_id.insert(mail.message_id.clone(), Rc::clone(mail)).is_some() {
 			println!("error: missing/duplicate Message-ID");
 			return Ok(());
 		}
-		for value in mail.get_header_values("References") {
-			for mid in value.split(' ').map(ToOwned::to_owned) {
-				threads.entry(mid).or_default().push(mail);
-			}
-		}
-		for value in mail.get_header_values("In-Reply-To") {
-			for mid in value.split(' ').map(ToOwned::to_owned) {
-				threads.entry(mid).or_default().push(mail);
-			}
+		if let Some(&thread) = thread_ids.get(&mail.message_id) {
+			let date = thread_dates.entry(thread).or_insert(mail.date);
+			*date = cmp::max(*date, mail.date);
 		}
 	}
-	let mut threads = threads.into_iter().collect_vec();
-	threads.sort_unstable_by_key(|(_, mails)| mails.len());
-	threads.reverse();
 	let mut graph = DiGraph::new();
 	let mut nodes = HashMap::new();
 	let mut nodes_inv = HashMap::new();
-	for i in 0..mails.len() {
-		let mail = &*mails[i];
-		let node = graph.add_node(mail);
-		nodes.insert(mail, node);
-		nodes_inv.insert(node, mail);
-	}
-	for i in 0..mails.len() {
-		let mail = &*mails[i];
-		for value in mail.get_header_values("In-Reply-To") {
-			for mid in value.split(' ') {
-				if let Some(other_mail) = mails_by_id.get(mid) {
-					graph.add_edge(nodes[other_mail], nodes[mail], ());
-				} else {
-					let pseudomail = Box::leak(Box::new(EasyMail::new_pseudo(mid.to_owned())));
-					let node = graph.add_node(pseudomail);
-					nodes.insert(pseudomail, node);
-					nodes_inv.insert(node, pseudomail);
-					graph.add_edge(node, nodes[mail], ());
-					mails_by_id.insert(mid.to_owned(), pseudomail);
-				}
+	for mail in &mails {
+		let node = graph.add_node(Rc::clone(mail));
+		nodes.insert(Rc::clone(mail), node);
+		nodes_inv.insert(node, Rc::clone(mail));
+	}
+	for mail in &mails {
+		for mid in mail.in_reply_to.split_whitespace() {
+			if let Some(other_mail) = mails_by_id.get(mid) {
+				graph.add_edge(nodes[other_mail], nodes[mail], ());
+			} else {
+				let pseudomail = Rc::new(EasyMail::new_pseudo(mid.to_owned()));
+				let node = graph.add_node(Rc::clone(&pseudomail));
+				nodes.insert(Rc::clone(&pseudomail), node);
+				nodes_inv.insert(node, Rc::clone(&pseudomail));
+				graph.add_edge(node, nodes[mail], ());
+				mails_by_id.insert(mid.to_owned(), pseudomail);
 			}
 		}
 	}
 	let mut roots = graph.node_references().filter(|x| graph.neighbors_directed(x.0, EdgeDirection::Incoming).count() == 0).collect_vec();
 	roots.sort_by_cached_key(|&(idx, mail)| {
 		let mut maximum = mail.date;
+		let mut thread = None;
 		let mut dfs = Dfs::new(&graph, idx);
 		while let Some(idx) = dfs.next(&graph) {
 			let other = &nodes_inv[&idx];
 			maximum = cmp::max(maximum, other.date);
+			thread = thread.or_else(|| thread_ids.get(&other.message_id).copied());
 		}
-		maximum
+		// parts of a thread that are only connected by References stay together
+		let thread_date = thread.map(|x| thread_dates[&x]).unwrap_or(maximum);
+		(thread_date, thread, maximum)
 	});
 	let mails_printed = RefCell::new(HashSet::new());
 
@@ -127,12 +139,12 @@ fn show_listing(mailbox: &str) -> Result<()> {
 		f: &'a dyn Fn(&PrintThread, NodeIndex, Placement, usize)
 	}
 	let print_thread = |this: &PrintThread, node, placement, parent| {
-		let mail = nodes_inv[&node];
-		if mails_printed.borrow().contains(&mail) { // TODO: placement == Placement::After ?
+		let mail = &nodes_inv[&node];
+		if mails_printed.borrow().contains(mail) { // TODO: placement == Placement::After ?
 			return;
 		}
-		let entry = tree.borrow_mut().insert_item(mail, placement, parent);
-		mails_printed.borrow_mut().insert(mail);
+		let entry = tree.borrow_mut().insert_item(TreeMail(Rc::clone(mail)), placement, parent);
+		mails_printed.borrow_mut().insert(Rc::clone(mail));
 		let mut replies = graph.neighbors_directed(node, EdgeDirection::Outgoing).collect_vec();
 		replies.sort_unstable_by_key(|&idx| {
 			let mut maximum = &nodes_inv[&idx].date;
@@ -164,16 +176,42 @@ fn show_listing(mailbox: &str) -> Result<()> {
 	} else {
 		(false, 0)
 	};
-	let tree_on_select = |siv: &mut Cursive, row| {
+	// mails are only read once they are opened
+	let opened: RefCell<HashMap<(Option<String>, MaildirID), Rc<EasyMail>>> = RefCell::new(HashMap::new());
+	let tree_on_select = Rc::new(move |siv: &mut Cursive, row: usize| {
 		let item = siv.call_on_name("tree", |tree: &mut MailTreeView| {
-			*tree.borrow_item(row).unwrap()
+			Rc::clone(&tree.borrow_item(row).unwrap().0)
 		}).unwrap();
 		if item.is_pseudo() {
 			return;
 		}
+		let tags = get_tags(db, &item.message_id).unwrap_or_default();
+		let key = (item.mailbox.clone(), item.id);
+		let cached = opened.borrow().get(&key).cloned();
+		let mail = match cached {
+			Some(x) => x,
+			None => match load_mail(maildir, &item) {
+				Ok(x) => {
+					let x = Rc::new(x);
+					opened.borrow_mut().insert(key, Rc::clone(&x));
+					x
+				},
+				Err(e) => {
+					error!("failed to open mail {}: {}", item.id, e);
+					return;
+				}
+			}
+		};
+		let parsed = match mail.parsed() {
+			Ok(x) => x,
+			Err(e) => {
+				error!("failed to parse mail {}: {}", item.id, e);
+				return;
+			}
+		};
 		let mut mail_struct = DiGraph::new();
-		item.get_tree_structure(&mut mail_struct, None);
-		if let Some(mail) = siv.call_on_name("part_select", |view: &mut TreeView<MailPart>| {
+		parsed.get_tree_structure(&mut mail_struct, None);
+		if let Some(mail_idx) = siv.call_on_name("part_select", |view: &mut TreeView<MailPart>| {
 			view.clear();
 			let mut part_to_display = None;
 			let mut idx_select = 0;
@@ -185,22 +223,22 @@ fn show_listing(mailbox: &str) -> Result<()> {
 				let incoming = mail_struct.neighbors_directed(idx, EdgeDirection::Incoming).next();
 				let tree_idx = if let Some(parent) = incoming {
 					let parent_idx = idxes[&parent];
-					let tree_idx = view.insert_item(MailPart::from(part), Placement::LastChild, parent_idx).unwrap();
+					let tree_idx = view.insert_item(MailPart::new(&mail, idx, part), Placement::LastChild, parent_idx).unwrap();
 					tree_idx
 				} else {
-					let tree_idx = view.insert_item(MailPart::from(part), Placement::After, i).unwrap();
+					let tree_idx = view.insert_item(MailPart::new(&mail, idx, part), Placement::After, i).unwrap();
 					i = tree_idx;
 					tree_idx
 				};
 				idxes.insert(idx, tree_idx);
 				if mime.starts_with("text/") {
 					if part_to_display.is_none() {
-						part_to_display = Some(part);
+						part_to_display = Some(idx);
 						idx_select = tree_idx;
 					} else if mime == "text/plain" {
-						if let Some(part) = part_to_display.as_ref() {
-							if part.ctype.mimetype != "text/plain" {
-								part_to_display = Some(part);
+						if let Some(&other) = part_to_display.as_ref() {
+							if mail_struct[other].ctype.mimetype != "text/plain" {
+								part_to_display = Some(idx);
 								idx_select = tree_idx;
 							}
 						}
@@ -213,17 +251,19 @@ fn show_listing(mailbox: &str) -> Result<()> {
 			part_to_display
 		}).unwrap() {
 			siv.call_on_name("mail_info", |view: &mut MailInfoView| {
-				view.set(item);
+				view.set(&parsed, tags);
 			});
+			let part = MailPart::new(&mail, mail_idx, mail_struct[mail_idx]);
 			siv.call_on_name("mail", |view: &mut MailPartView| {
-				view.set_part(mail);
+				view.set_part(part);
 			});
 		}
-	};
+	});
 	tree.set_on_submit(|siv, _row| {
 		siv.focus_name("mail").unwrap();
 	});
-	let tree = tree.on_select(tree_on_select).with_name("tree").scrollable().with_name("tree_scroller");
+	let on_select = Rc::clone(&tree_on_select);
+	let tree = tree.on_select(move |siv, row| on_select(siv, row)).with_name("tree").scrollable().with_name("tree_scroller");
 	let update_flags2 = Arc::clone(&update_flags);
 	let update_flags3 = Arc::clone(&update_flags);
 	let update_flags4 = Arc::clone(&update_flags);
@@ -235,8 +275,7 @@ fn show_listing(mailbox: &str) -> Result<()> {
 					let mail = tree.borrow_item_mut(r).unwrap();
 					mail.mark_as_read(true);
 					// TODO error handling
-					let _ = mail.save_flags(&maildir);
-					let _ = update_flags2.lock().execute(params![mail.get_flags(), mail.id.to_i64()]);
+					let _ = save_flags(mail, maildir, mailbox, &update_flags2);
 				}
 			});
 		})
@@ -246,8 +285,7 @@ fn show_listing(mailbox: &str) -> Result<()> {
 					let mail = tree.borrow_item_mut(r).unwrap();
 					mail.mark_as_read(false);
 					// TODO error handling
-					let _ = mail.save_flags(&maildir);
-					let _ = update_flags3.lock().execute(params![mail.get_flags(), mail.id.to_i64()]);
+					let _ = save_flags(mail, maildir, mailbox, &update_flags3);
 				}
 			});
 		})
@@ -258,8 +296,7 @@ fn show_listing(mailbox: &str) -> Result<()> {
 					mail.mark_as_read(true);
 					mail.add_flag2(TRASHED);
 					// TODO error handling
-					let _ = mail.save_flags(&maildir);
-					let _ = update_flags4.lock().execute(params![mail.get_flags(), mail.id.to_i64()]);
+					let _ = save_flags(mail, maildir, mailbox, &update_flags4);
 				}
 			});
 		})
@@ -269,11 +306,12 @@ fn show_listing(mailbox: &str) -> Result<()> {
 					let mail = tree.borrow_item_mut(r).unwrap();
 					mail.add_flag2(DELETE);
 					// TODO error handling
-					let _ = mail.save_flags(&maildir);
-					let _ = update_flags5.lock().execute(params![mail.get_flags(), mail.id.to_i64()]);
+					let _ = save_flags(mail, maildir, mailbox, &update_flags5);
 				}
 			});
-		});
+		})
+		.on_event('+', move |siv| edit_tag(siv, db, true))
+		.on_event('-', move |siv| edit_tag(siv, db, false));
 	let tree_resized = ResizedView::new(SizeConstraint::Fixed(120), SizeConstraint::Full, tree);
 	let mail_info = MailInfoView::new().with_name("mail_info");
 	let mail_content = MailPartView::empty().with_name("mail");
@@ -309,7 +347,7 @@ fn show_listing(mailbox: &str) -> Result<()> {
 		})
 		.on_event('s', |s| {
 			if let Some((bytes, name)) = s.call_on_name("mail", |mail: &mut MailPartView| {
-				mail.part.map(|x| (x.get_body_raw().unwrap(), x.get_content_disposition().params.get("filename").cloned()))
+				mail.part.as_ref()?.with(|x| (x.get_body_raw().unwrap(), x.get_content_disposition().params.get("filename").cloned())).ok()
 			}).flatten() {
 				let mut default_path = CONFIG.get().unwrap().read().browse.base_save_path.display().to_string();
 				if let Some(name) = name {
@@ -349,7 +387,7 @@ fn show_listing(mailbox: &str) -> Result<()> {
 	let mut mail_part_select = TreeView::<MailPart>::new();
 	mail_part_select.set_on_select(|siv, row| {
 		let mail = siv.call_on_name("part_select", |tree: &mut TreeView<MailPart>| {
-			tree.borrow_item(row).unwrap().part
+			tree.borrow_item(row).unwrap().clone()
 		}).unwrap();
 		siv.call_on_name("mail", |view: &mut MailView| {
 			view.set_part(mail);
@@ -359,7 +397,7 @@ fn show_listing(mailbox: &str) -> Result<()> {
 		siv.focus_name("mail").unwrap();
 	});
 	let mail_wrapper = LinearLayout::vertical()
-		.child(ResizedView::new(SizeConstraint::Full, SizeConstraint::Fixed(5), Panel::new(mail_info).title("Mail")))
+		.child(ResizedView::new(SizeConstraint::Full, SizeConstraint::Fixed(6), Panel::new(mail_info).title("Mail")))
 		.child(ResizedView::with_full_screen(mail_content))
 		.child(Panel::new(mail_part_select.with_name("part_select"))
 			.title("Multipart selection"));
@@ -371,6 +409,10 @@ fn show_listing(mailbox: &str) -> Result<()> {
 	if tree_present {
 		tree_on_select(&mut siv, last_row); // show selected mail
 	}
+	// the new mail is displayed in the tree (highlighted), move it out of 'new'
+	for mail in mails.iter().filter(|x| x.new && x.mailbox.is_none()) {
+		maildir.move_new_to_cur(&mail.id.to_string())?;
+	}
 
 	let mut setup = LinearLayout::vertical();
 	{
@@ -439,33 +481,129 @@ fn show_listing(mailbox: &str) -> Result<()> {
 	Ok(())
 }
 
+/// Find the file of a mail, which might be stored in another mailbox than the one listed.
+fn load_mail(maildir: &Maildir, mail: &EasyMail) -> Result<EasyMail> {
+	let mut loaded = match mail.mailbox.as_ref() {
+		Some(mailbox) => get_maildir(mailbox)?.find_mail(mail.id)?,
+		None => maildir.find_mail(mail.id)?
+	};
+	loaded.mailbox = mail.mailbox.clone();
+	Ok(loaded)
+}
+
+/// Store the flags of a mail in its maildir and the database.
+fn save_flags(mail: &EasyMail, maildir: &Maildir, mailbox: &str, update_flags: &Mutex<Statement>) -> Result<()> {
+	if let Some(mailbox) = mail.mailbox.as_ref() {
+		mail.save_flags(&get_maildir(mailbox)?)?;
+	} else {
+		mail.save_flags(maildir)?;
+	}
+	let mailbox = mail.mailbox.as_deref().unwrap_or(mailbox);
+	update_flags.lock().execute(params![mail.get_flags(), mailbox, mail.id.uid_validity, mail.id.uid])?;
+	Ok(())
+}
+
+/// Ask for a tag to add to (or remove from) the selected mail.
+fn edit_tag(siv: &mut Cursive, db: &'static Connection, add: bool) {
+	let message_id = match siv.call_on_name("tree", |tree: &mut MailTreeView| {
+		tree.row().map(|r| Rc::clone(&tree.borrow_item(r).unwrap().0))
+	}).flatten() {
+		Some(mail) if !mail.is_pseudo() => mail.message_id.clone(),
+		_ => return
+	};
+	siv.add_layer(
+		Dialog::new()
+			.title(if add { "Add tag" } else { "Remove tag" })
+			.padding_lrtb(1, 1, 1, 0)
+			.content(
+				EditView::new()
+					.on_submit(move |s, tag| {
+						s.pop_layer();
+						let result = if add {
+							add_tag(db, &message_id, tag)
+						} else {
+							remove_tag(db, &message_id, tag)
+						};
+						match result.and_then(|_| get_tags(db, &message_id)) {
+							Ok(tags) => {
+								s.call_on_name("mail_info", |view: &mut MailInfoView| {
+									view.set_tags(tags);
+								});
+							},
+							Err(e) => s.add_layer(Dialog::info(e.to_string()))
+						}
+					})
+					.fixed_width(40),
+			)
+			.dismiss_button("Cancel"),
+	);
+}
+
 type MailScrollerView = OnEventView<NamedView<MailView>>;
 type MailView = MailPartView;
-type MailTreeView<'a> = TreeView<&'a EasyMail<'a>>;
+type MailTreeView = TreeView<TreeMail>;
 
+/// A mail shown in the tree, shared with the thread graph.
 #[derive(Debug)]
-struct MailPart {
-	part: &'static ParsedMail<'static>
+struct TreeMail(Rc<EasyMail>);
+
+impl Deref for TreeMail {
+	type Target = EasyMail;
+
+	fn deref(&self) -> &EasyMail {
+		&self.0
+	}
 }
 
-impl Display for MailPart {
+impl Display for TreeMail {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		write!(f, "{}", self.part.ctype.mimetype)
+		write!(f, "{}", self.0)
+	}
+}
+
+impl TreeEntry for TreeMail {
+	fn display(&self, width: usize) -> SpannedString<Style> {
+		(&*self.0).display(width)
 	}
 }
 
-impl From<&'static ParsedMail<'static>> for MailPart {
-	fn from(part: &'static ParsedMail<'static>) -> Self {
+/// A part of an opened mail, found again by parsing the mail.
+#[derive(Debug, Clone)]
+struct MailPart {
+	mail: Rc<EasyMail>,
+	/// index in the graph of [MailExtension::get_tree_structure]
+	idx: NodeIndex,
+	mimetype: String
+}
+
+impl MailPart {
+	fn new(mail: &Rc<EasyMail>, idx: NodeIndex, part: &ParsedMail) -> Self {
 		Self {
-			part
+			mail: Rc::clone(mail),
+			idx,
+			mimetype: part.ctype.mimetype.clone()
 		}
 	}
+
+	/// Parse the mail and call `f` with this part.
+	fn with<T>(&self, f: impl FnOnce(&ParsedMail) -> T) -> Result<T> {
+		let parsed = self.mail.parsed()?;
+		let mut mail_struct = DiGraph::new();
+		parsed.get_tree_structure(&mut mail_struct, None);
+		Ok(f(mail_struct[self.idx]))
+	}
+}
+
+impl Display for MailPart {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.mimetype)
+	}
 }
 
 impl TreeEntry for MailPart {}
 
 struct MailPartView {
-	part: Option<&'static ParsedMail<'static>>,
+	part: Option<MailPart>,
 	wrap: WrapMethod,
 	scroll: bool,
 	text: Option<ScrollView<TextView>>,
@@ -502,7 +640,7 @@ impl MailPartView {
 		}
 	}
 
-	fn set_part(&mut self, part: &'static ParsedMail<'static>) {
+	fn set_part(&mut self, part: MailPart) {
 		self.part = Some(part);
 		self.text = None;
 		self.cached_size = None;
@@ -511,11 +649,11 @@ impl MailPartView {
 	}
 
 	fn setup_text(&mut self, size: Vec2) {
-		if self.part.is_none() {
-			return;
-		}
-		let part = self.part.unwrap();
-		let body = if part.ctype.mimetype == "text/html" {
+		let part = match self.part.as_ref() {
+			Some(x) => x,
+			None => return
+		};
+		let body = part.with(|part| if part.ctype.mimetype == "text/html" {
 			let html = part.get_body().unwrap();
 			eprintln!("HTML layout using {} width, length {:?}", size.x, html.len());
 			html2text::from_read(html.as_bytes(), size.x)
@@ -523,7 +661,7 @@ impl MailPartView {
 			part.get_body().unwrap()
 		} else {
 			"binary data".into()
-		};
+		}).unwrap_or_else(|e| format!("failed to parse mail: {}", e));
 		let mut text = TextView::new(body);
 		text.set_wrap_method(self.wrap);
 		let text = text.scrollable()
@@ -610,18 +748,26 @@ impl View for MailPartView {
 }
 
 struct MailInfoView {
-	email: Option<&'static ParsedMail<'static>>
+	/// values of [HEADERS_TO_DISPLAY]
+	headers: Option<Vec<String>>,
+	tags: Vec<String>
 }
 
 impl MailInfoView {
 	fn new() -> Self {
 		Self {
-			email: None
+			headers: None,
+			tags: Vec::new()
 		}
 	}
 
-	fn set(&mut self, mail: &'static ParsedMail<'static>) {
-		self.email = Some(mail);
+	fn set(&mut self, mail: &ParsedMail, tags: Vec<String>) {
+		self.headers = Some(HEADERS_TO_DISPLAY.iter().map(|x| mail.headers.get_all_values(x).join(" ")).collect());
+		self.tags = tags;
+	}
+
+	fn set_tags(&mut self, tags: Vec<String>) {
+		self.tags = tags;
 	}
 }
 
@@ -629,21 +775,23 @@ const HEADERS_TO_DISPLAY: &[&str] = &["From", "Subject", "To"];
 
 impl View for MailInfoView {
 	fn draw(&self, printer: &cursive::Printer) {
-		if let Some(mail) = self.email {
+		if let Some(values) = self.headers.as_ref() {
 			let mut y = 0;
-			for header in HEADERS_TO_DISPLAY {
+			for (header, value) in HEADERS_TO_DISPLAY.iter().zip(values) {
 				let mut x = 0;
 				printer.print((x, y), header);
 				x += header.len(/* ASCII-only */);
 				printer.print((x, y), ": ");
 				x += 2;
-				printer.print((x, y), &mail.headers.get_all_values(header).join(" "));
+				printer.print((x, y), value);
 				y += 1;
 			}
+			printer.print((0, y), "Tags: ");
+			printer.print((6, y), &self.tags.join(" "));
 		}
 	}
 
 	fn required_size(&mut self, _constraint: Vec2) -> Vec2 {
-		(42, HEADERS_TO_DISPLAY.len()).into()
+		(42, HEADERS_TO_DISPLAY.len() + 1).into()
 	}
 }
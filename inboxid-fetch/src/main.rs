@@ -1,45 +1,78 @@
-use std::{cmp, env, time::Duration};
+use std::{cmp, panic, sync::mpsc, thread, time::Duration};
 
 use itertools::Itertools;
 use maildir::Maildir;
 
 use inboxid_lib::*;
-use mailparse::{MailHeaderMap, parse_headers};
-use rusqlite::params;
+use mailparse::parse_mail;
+use rusqlite::{Connection, params};
+
+/// Delay before the first reconnection attempt, doubled after every failure.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// Interval between NOOPs if the server does not support IDLE.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Re-issue IDLE before the server logs us out (RFC 2177 recommends 29 minutes).
+const IDLE_KEEPALIVE: Duration = Duration::from_secs(29 * 60);
 
 fn main() -> Result<()> {
-	let host = env::var("MAILHOST").expect("missing envvar MAILHOST");
-	let user = env::var("MAILUSER").expect("missing envvar MAILUSER");
-	let password = env::var("MAILPASSWORD").expect("missing envvar MAILPASSWORD");
-	let maildir = get_maildir("INBOX")?;
-	let port = 993;
+	let mut args = get_args()?.into_iter().skip(1).peekable();
+	let idle = args.peek().map(|x| x == "--idle").unwrap_or(false);
+	if idle {
+		args.next();
+	}
+	let account = account()?;
+
+	if idle {
+		let mut mailboxes = vec!["INBOX".to_owned()];
+		for mailbox in account.idle_mailboxes.iter().cloned().chain(args) {
+			if !mailboxes.contains(&mailbox) {
+				mailboxes.push(mailbox);
+			}
+		}
+		return watch(account, mailboxes);
+	}
 
-	fetch_inbox_top(&host, &user, &password, port, "INBOX", maildir)
+	let maildir = account.get_maildir("INBOX")?;
+	fetch_inbox_top(account, "INBOX", maildir)
 }
 
 fn fetch_inbox_top(
-	host: &str,
-	user: &str,
-	password: &str,
-	port: u16,
+	account: &Account,
 	mailbox: &str,
 	maildir: Maildir,
 ) -> Result<()> {
-	let db = get_db()?;
-	let mut imap_session = connect(host, port, user, password)?;
+	let db = account.get_db()?;
+	let mut imap_session = ReconnectingSession::connect(account)?;
 	println!("getting capabilities..");
-	let caps = imap_session.capabilities()?;
+	let caps = imap_session.retry(|s| s.capabilities())?;
 	println!("capabilities: {}", caps.iter().map(|x| format!("{:?}", x)).join(" "));
 
 	while let Ok(x) = imap_session.unsolicited_responses.recv_timeout(Duration::from_millis(50)) {
 		println!("aah what is this: {:?}", x);
 	}
 
+	fetch_new(&mut imap_session, &db, mailbox, mailbox, &maildir)?;
+
+	// be nice to the server and log out
+	imap_session.logout()?;
+
+	Ok(())
+}
+
+/// Fetch all mail that arrived in the mailbox since the last run (`server` is its name on the server).
+/// The mailbox stays selected (read-only) afterwards.
+fn fetch_new(
+	imap_session: &mut ReconnectingSession,
+	db: &Connection,
+	mailbox: &str,
+	server: &str,
+	maildir: &Maildir,
+) -> Result<()> {
 	// we want to fetch the first email in the INBOX mailbox
-	let resp = imap_session.examine(mailbox)?;
-	// TODO(errors)
-	let uid_validity = resp.uid_validity.unwrap();
-	let uid_next = resp.uid_next.unwrap();
+	let resp = imap_session.examine(server)?;
+	let uid_validity = resp.uid_validity.ok_or_else(|| InboxidError::imap(mailbox, imap::Error::Bad("no UIDVALIDITY in EXAMINE response".to_owned())))?;
+	let uid_next = resp.uid_next.ok_or_else(|| InboxidError::imap(mailbox, imap::Error::Bad("no UIDNEXT in EXAMINE response".to_owned())))?;
 	println!("uid: {} {}", uid_validity, uid_next);
 
 	let (prev_uid_validity, prev_uid) = maildir.get_file(".uid").map(
@@ -58,37 +91,104 @@ fn fetch_inbox_top(
 	} else if uid_next != prev_uid + 1 {
 		fetch_range = format!("{}:*", prev_uid + 1);
 	} else {
-		println!("no new mail.");
-		imap_session.logout()?;
+		println!("no new mail in {}.", mailbox);
 		return Ok(());
 	}
 	println!("fetching {:?}", fetch_range);
 
-	let messages = imap_session.uid_fetch(&fetch_range, "RFC822")?;
+	let messages = imap_session.retry(|s| s.uid_fetch(&fetch_range, "RFC822"))?;
 	let mut largest_uid = prev_uid;
 
-	let mut save_mail = db.prepare("INSERT INTO mail VALUES (?,?,?)")?;
+	let mut save_mail = db.prepare("INSERT OR REPLACE INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)")?;
 
 	for mail in messages.iter() {
 		let uid = mail.uid.unwrap();
+		// "n:*" always includes the last mail, even if it is older
+		if uid_validity == prev_uid_validity && uid <= prev_uid {
+			continue;
+		}
 		largest_uid = cmp::max(largest_uid, uid);
 		println!("mail {:?}", uid);
-		let id = gen_id(uid_validity, uid);
-		let uid = ((uid_validity as u64) << 32) | uid as u64;
-		if !maildir.exists(&id) {
+		let id = MaildirID::new(uid_validity, uid);
+		let id_name = id.to_string();
+		if !maildir.exists(&id_name) {
 			let mail_data = mail.body().unwrap_or_default();
-			maildir.store_new_with_id(&id, mail_data)?;
+			maildir.store_new_with_id(&id_name, mail_data).map_err(|e| InboxidError::maildir(mailbox, id, e))?;
 
-			let headers = parse_headers(&mail_data)?.0;
-			let message_id = headers.get_all_values("Message-ID").join(" ");
-			save_mail.execute(params![mailbox, store_i64(uid), message_id])?;
+			let parsed = parse_mail(&mail_data)?;
+			let message_id = parsed.get_headers().message_id(mailbox, id);
+			save_mail.execute(params![mailbox, id.uid_validity, id.uid, message_id, ""])?;
+			let summary = MailSummary::new(mailbox, id, "", &parsed, mail_data.len() as u64, maildir.find_filename(&id_name).as_deref());
+			save_summary(db, mailbox, &summary)?;
+			harvest_addresses(db, &summary, &parsed)?;
+			index_mail(db, mailbox, id, &parsed)?;
 		}
 	}
 	let uid = cmp::max(uid_next - 1, largest_uid);
 	maildir.save_file(".uid", &format!("{},{}", uid_validity, uid))?;
 
-	// be nice to the server and log out
-	imap_session.logout()?;
-
 	Ok(())
 }
+
+/// Keep watching the mailboxes for new mail, using one connection per mailbox.
+/// Returns the first error of any watcher.
+fn watch(account: &'static Account, mailboxes: Vec<String>) -> Result<()> {
+	let (tx, rx) = mpsc::channel();
+	for mailbox in mailboxes {
+		let tx = tx.clone();
+		thread::spawn(move || {
+			let result = panic::catch_unwind(|| watch_mailbox(account, &mailbox))
+				.unwrap_or_else(|_| Err(InboxidError::Other(format!("watcher of {} panicked", mailbox).into())));
+			let _ = tx.send(result);
+		});
+	}
+	drop(tx);
+	rx.recv().unwrap_or(Ok(()))
+}
+
+/// Watch a single mailbox, reconnecting with exponential backoff if the connection fails.
+/// Only returns on configuration errors.
+fn watch_mailbox(account: &Account, mailbox: &str) -> Result<()> {
+	let mut backoff = MIN_BACKOFF;
+	loop {
+		match watch_session(account, mailbox, &mut backoff) {
+			Err(e @ InboxidError::Config(_)) => return Err(e),
+			Err(e) => println!("{}: {}, reconnecting in {}s", mailbox, e, backoff.as_secs()),
+			Ok(()) => {}
+		}
+		thread::sleep(backoff);
+		backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+	}
+}
+
+fn watch_session(account: &Account, mailbox: &str, backoff: &mut Duration) -> Result<()> {
+	let db = account.get_db()?;
+	// other watchers and inboxid-sync may write at the same time
+	db.busy_timeout(Duration::from_secs(30))?;
+	let maildir = account.get_maildir(mailbox)?;
+	let mut imap_session = ReconnectingSession::connect(account)?;
+	let can_idle = imap_session.retry(|s| s.capabilities())?.has_str("IDLE");
+	if !can_idle {
+		println!("{}: server does not support IDLE, polling every {}s", mailbox, POLL_INTERVAL.as_secs());
+	}
+	let server = server_mailbox_name(&mut imap_session, mailbox)?;
+	loop {
+		fetch_new(&mut imap_session, &db, mailbox, &server, &maildir)?;
+		// the connection works, retry quickly if it fails later
+		*backoff = MIN_BACKOFF;
+
+		if can_idle {
+			let mut handle = imap_session.idle().map_err(|e| InboxidError::imap(mailbox, e))?;
+			handle.set_keepalive(IDLE_KEEPALIVE);
+			// returns once the server reports EXISTS, EXPUNGE, FETCH, ..
+			handle.wait_keepalive().map_err(|e| InboxidError::imap(mailbox, e))?;
+		} else {
+			thread::sleep(POLL_INTERVAL);
+			imap_session.retry(|s| s.noop())?;
+		}
+		// new mail is detected using UIDNEXT, flag changes and expunges are left to inboxid-sync
+		while let Ok(x) = imap_session.unsolicited_responses.try_recv() {
+			println!("{}: {:?}", mailbox, x);
+		}
+	}
+}
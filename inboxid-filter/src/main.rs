@@ -1,40 +1,32 @@
-use std::env;
-
-use anyhow::anyhow;
 use inboxid_lib::*;
-use itertools::Itertools;
 use mailproc::Config;
 
 fn main() -> Result<()> {
-	let args = env::args().collect_vec();
+	let args = get_args()?;
 	if args.len() < 3 {
-		Err(anyhow!("required arguments: mailbox name, filter file path"))?;
-		unreachable!()
+		Err(InboxidError::Config("required arguments: mailbox name, filter file path".to_owned()))
 	} else {
 		do_filtering(&args[1], &args[2])
 	}
 }
 
 fn do_filtering(mailbox: &str, config: &str) -> Result<()> {
-	let config = Config::load_from_path(config)?;
+	let config = Config::load_from_path(config).map_err(|e| InboxidError::Config(format!("failed to load filter rules: {}", e)))?;
 
 	let maildir = get_maildir(mailbox)?;
 
-	let mut mails = Vec::new();
-	for x in maildir.list_cur() {
-		mails.push(x?);
-	}
-	let mut mails = maildir.get_mails(&mut mails)?;
-	mails.sort_by_key(|x| x.id);
+	let mut mails = maildir.mails().collect_parallel()?;
+	mails.retain(|x| !x.new);
 	
 	let mut imap_session = get_imap_session()?;
-	imap_session.select(mailbox)?;
+	let server_mailbox = server_mailbox_name(&mut imap_session, mailbox)?;
+	imap_session.select(&server_mailbox).map_err(|e| InboxidError::imap(mailbox, e))?;
 
 	for mail in mails {
 		if mail.has_flag2(TRASHED) || mail.has_flag2(DELETE) {
 			continue; // ignore mails marked for deletion
 		}
-		if let Some(action) = mailproc::handle(&mail, &[], &config) { // TODO: provide raw bytes
+		if let Some(action) = mailproc::handle(&mail.parsed()?, &[], &config) { // TODO: provide raw bytes
 			println!("{:?}", action.0);
 			println!(" matched {}", mail.subject);
 			for action in action.0.action.as_ref().unwrap() {
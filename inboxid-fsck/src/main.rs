@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use imap::types::NameAttribute;
+use inboxid_lib::*;
+use rusqlite::params;
+
+fn main() -> Result<()> {
+	let mut repair = false;
+	let mut offline = false;
+	let mut mailboxes = Vec::new();
+	for arg in get_args()?.into_iter().skip(1) {
+		match &*arg {
+			"--repair" => repair = true,
+			"--offline" => offline = true,
+			_ if arg.starts_with("--") => return Err(InboxidError::Config("usage: inboxid-fsck [--repair] [--offline] [mailbox...]".to_owned())),
+			_ => mailboxes.push(arg),
+		}
+	}
+	let account = account()?;
+	let db = get_db()?;
+
+	// UIDVALIDITY of every mailbox on the server
+	let mut server = BTreeMap::new();
+	if !offline {
+		let mut imap_session = ReconnectingSession::connect(account)?;
+		let names = imap_session.retry(|s| s.list(None, Some("*")))?.iter()
+			// these can not be examined
+			.filter(|x| !x.attributes().iter().any(|x| *x == NameAttribute::NoSelect || *x == NameAttribute::Custom("\\NonExistent".into())))
+			.map(|x| (x.name().to_owned(), local_mailbox_name(x.name(), x.delimiter())))
+			.collect::<Vec<_>>();
+		for (name, local) in names {
+			if !mailboxes.is_empty() && !mailboxes.contains(&local) {
+				continue;
+			}
+			let mailbox = imap_session.examine(&name)?;
+			server.insert(local, mailbox.uid_validity);
+		}
+		imap_session.logout()?;
+	}
+
+	if mailboxes.is_empty() {
+		let mut all = BTreeSet::new();
+		all.extend(server.keys().cloned());
+		all.extend(account.get_maildirs()?);
+		let mut stmt = db.prepare("SELECT DISTINCT mailbox FROM mail")?;
+		for mailbox in stmt.query_map(params![], |row| row.get(0))? {
+			all.insert(mailbox?);
+		}
+		mailboxes = all.into_iter().collect();
+	}
+
+	let mut problems = Vec::new();
+	for mailbox in &mailboxes {
+		println!("checking {}..", mailbox);
+		let uid_validity = server.get(mailbox).copied().flatten();
+		problems.extend(check_mailbox(&db, account, mailbox, uid_validity)?);
+	}
+	println!("checking {}..", GONE);
+	problems.extend(check_gone(&db, account)?);
+
+	for problem in &problems {
+		println!("{}", problem);
+		if repair {
+			println!("  repairing: {}", problem.repair_description());
+			problem.repair(&db, account)?;
+		} else {
+			println!("  repair: {}", problem.repair_description());
+		}
+	}
+	if problems.is_empty() {
+		println!("no problems found");
+	} else if !repair {
+		println!("{} problem(s) found, run with --repair to fix them", problems.len());
+	}
+	Ok(())
+}
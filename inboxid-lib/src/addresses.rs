@@ -0,0 +1,90 @@
+use mailparse::{MailAddr, MailHeaderMap, ParsedMail, SingleInfo, addrparse};
+use rusqlite::{Connection, params};
+
+use crate::{MailSummary, Result};
+
+/// Headers harvested for the address book.
+const ADDRESS_HEADERS: &[&str] = &["From", "To", "Cc"];
+
+/// An entry of the address book.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+	/// email address, in lowercase
+	pub address: String,
+	/// most recently used display name (may be empty)
+	pub name: String,
+	/// date of the newest mail mentioning the address, as unix timestamp
+	pub last_seen: i64,
+	/// number of mails mentioning the address
+	pub count: u32,
+}
+
+/// Parse all addresses of an address list header, including group members.
+/// Invalid headers result in an empty list.
+pub fn parse_addresses(value: &str) -> Vec<SingleInfo> {
+	let mut addresses = Vec::new();
+	for addr in addrparse(value).map(|x| x.to_vec()).unwrap_or_default() {
+		match addr {
+			MailAddr::Single(info) => addresses.push(info),
+			MailAddr::Group(group) => addresses.extend(group.addrs),
+		}
+	}
+	addresses
+}
+
+/// Add the addresses of a stored mail to the address book.
+/// Every message is only counted once, even if it is stored in several mailboxes.
+pub fn harvest_addresses(conn: &Connection, summary: &MailSummary, mail: &ParsedMail) -> Result<()> {
+	let headers = mail.get_headers();
+	for header in ADDRESS_HEADERS {
+		for value in headers.get_all_values(header) {
+			for info in parse_addresses(&value) {
+				let address = info.addr.trim().to_lowercase();
+				if address.is_empty() {
+					continue;
+				}
+				let name = info.display_name.unwrap_or_default();
+				let new = conn.prepare_cached("INSERT OR IGNORE INTO address_messages (address, message_id) VALUES (?,?)")?
+					.execute(params![address, summary.message_id])?;
+				if new == 0 {
+					continue;
+				}
+				conn.prepare_cached("INSERT INTO addresses (address, name, last_seen, count) VALUES (?,?,?,1)
+					ON CONFLICT(address) DO UPDATE SET
+						count = count + 1,
+						name = CASE WHEN excluded.name != '' AND excluded.last_seen >= last_seen THEN excluded.name ELSE name END,
+						last_seen = MAX(last_seen, excluded.last_seen)")?
+					.execute(params![address, name, summary.date])?;
+			}
+		}
+	}
+	Ok(())
+}
+
+fn address_from_row(row: &rusqlite::Row) -> rusqlite::Result<Address> {
+	Ok(Address {
+		address: row.get(0)?,
+		name: row.get(1)?,
+		last_seen: row.get(2)?,
+		count: row.get(3)?,
+	})
+}
+
+/// Get the whole address book, sorted by address.
+pub fn all_addresses(conn: &Connection) -> Result<Vec<Address>> {
+	let mut stmt = conn.prepare("SELECT address, name, last_seen, count FROM addresses ORDER BY address")?;
+	let addresses = stmt.query_map(params![], address_from_row)?.collect::<rusqlite::Result<_>>()?;
+	Ok(addresses)
+}
+
+/// Find addresses starting with the prefix, or with a display name containing a word starting with it.
+/// The most frequently used addresses are returned first.
+pub fn complete_address(conn: &Connection, prefix: &str, limit: u32) -> Result<Vec<Address>> {
+	let escaped = prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+	let mut stmt = conn.prepare("SELECT address, name, last_seen, count FROM addresses
+		WHERE address LIKE ?1 ESCAPE '\\' OR name LIKE ?1 ESCAPE '\\' OR name LIKE ?2 ESCAPE '\\'
+		ORDER BY count DESC, last_seen DESC LIMIT ?3")?;
+	let addresses = stmt.query_map(params![format!("{}%", escaped), format!("% {}%", escaped), limit], address_from_row)?
+		.collect::<rusqlite::Result<_>>()?;
+	Ok(addresses)
+}
@@ -0,0 +1,139 @@
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::{InboxidError, Result, rebuild_threads};
+
+/// Schema migrations of the mail index, applied in order.
+/// Migration `n` upgrades the database to schema version `n + 1`.
+/// Never change a released migration, append a new one instead.
+const MIGRATIONS: &[&str] = &[
+	// 1: the mail table (created without version tracking by older versions)
+	"CREATE TABLE IF NOT EXISTS mail(
+		mailbox STRING NOT NULL,
+		uid INTEGER NOT NULL,
+		message_id STRING NOT NULL,
+		flags STRING NOT NULL
+	);",
+	// 2: remote state as of the last sync, used to only fetch changes (CONDSTORE)
+	"CREATE TABLE IF NOT EXISTS mailbox_state(
+		mailbox STRING PRIMARY KEY NOT NULL,
+		uid_validity INTEGER NOT NULL,
+		highest_modseq INTEGER NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS remote_mail(
+		mailbox STRING NOT NULL,
+		uid INTEGER NOT NULL,
+		message_id STRING NOT NULL,
+		flags STRING NOT NULL,
+		PRIMARY KEY (mailbox, uid)
+	);",
+	// 3: split the packed (uid_validity << 32 | uid) column, drop duplicate rows
+	"CREATE TABLE mail_new(
+		mailbox STRING NOT NULL,
+		uid_validity INTEGER NOT NULL,
+		uid INTEGER NOT NULL,
+		message_id STRING NOT NULL,
+		flags STRING NOT NULL,
+		UNIQUE(mailbox, uid_validity, uid)
+	);
+	INSERT OR IGNORE INTO mail_new
+		SELECT mailbox, (uid >> 32) & 4294967295, uid & 4294967295, message_id, flags FROM mail;
+	DROP TABLE mail;
+	ALTER TABLE mail_new RENAME TO mail;
+	CREATE INDEX mail_message_id ON mail(message_id);",
+	// 4: full-text search, rows of search_index are identified by search_doc.id
+	"CREATE TABLE search_doc(
+		id INTEGER PRIMARY KEY,
+		mailbox STRING NOT NULL,
+		uid_validity INTEGER NOT NULL,
+		uid INTEGER NOT NULL,
+		UNIQUE(mailbox, uid_validity, uid)
+	);
+	CREATE VIRTUAL TABLE search_index USING fts5(subject, sender, recipients, body);",
+	// 5: cached header summaries (see MailSummary), NULL if not cached yet
+	"ALTER TABLE mail ADD COLUMN subject STRING;
+	ALTER TABLE mail ADD COLUMN sender STRING;
+	ALTER TABLE mail ADD COLUMN date INTEGER;
+	ALTER TABLE mail ADD COLUMN in_reply_to STRING;
+	ALTER TABLE mail ADD COLUMN refs STRING;
+	ALTER TABLE mail ADD COLUMN size INTEGER;
+	ALTER TABLE mail ADD COLUMN has_attachment INTEGER;",
+	// 6: thread index, also contains referenced messages that are not stored (yet)
+	"CREATE TABLE threads(
+		message_id STRING PRIMARY KEY NOT NULL,
+		thread_id INTEGER NOT NULL
+	);
+	CREATE INDEX threads_thread_id ON threads(thread_id);",
+	// 7: address book, address_messages makes sure every message is only counted once
+	"CREATE TABLE addresses(
+		address STRING PRIMARY KEY NOT NULL,
+		name STRING NOT NULL,
+		last_seen INTEGER NOT NULL,
+		count INTEGER NOT NULL
+	);
+	CREATE TABLE address_messages(
+		address STRING NOT NULL,
+		message_id STRING NOT NULL,
+		PRIMARY KEY (address, message_id)
+	);",
+	// 8: user tags, synced_tags is the state of the last sync (used to merge changes)
+	"CREATE TABLE tags(
+		message_id STRING NOT NULL,
+		tag STRING NOT NULL,
+		PRIMARY KEY (message_id, tag)
+	);
+	CREATE INDEX tags_tag ON tags(tag);
+	CREATE TABLE synced_tags(
+		message_id STRING NOT NULL,
+		tag STRING NOT NULL,
+		PRIMARY KEY (message_id, tag)
+	);",
+	// 9: source of the date, mails without valid Date header are parsed again to use a fallback
+	"ALTER TABLE mail ADD COLUMN date_source INTEGER;
+	UPDATE mail SET date_source = 0 WHERE subject IS NOT NULL;
+	UPDATE mail SET subject = NULL WHERE date = 0;",
+	// 10: synced_flags is the state of the last sync (used to merge changes), NULL if unknown
+	"ALTER TABLE mail ADD COLUMN synced_flags STRING;",
+];
+
+/// Data migrations that can't be expressed in SQL,
+/// applied after the migration to the given schema version.
+fn migrate_data(conn: &Connection, version: u32) -> Result<()> {
+	match version {
+		// add the mails cached before to the thread index
+		6 => rebuild_threads(conn),
+		_ => Ok(()),
+	}
+}
+
+/// Schema version created by this version of inboxid.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Get the schema version of the database (0 if it was never migrated).
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+	conn.execute("CREATE TABLE IF NOT EXISTS schema_version(version INTEGER NOT NULL)", params![])?;
+	let version = conn.query_row("SELECT version FROM schema_version", params![], |row| row.get(0)).optional()?;
+	Ok(version.unwrap_or(0))
+}
+
+/// Bring the database schema up to date.
+/// Fails if the database was created by a newer version of inboxid.
+pub fn migrate(conn: &mut Connection) -> Result<()> {
+	let version = schema_version(conn)?;
+	if version > SCHEMA_VERSION {
+		return Err(InboxidError::UnsupportedSchema {
+			version,
+			supported: SCHEMA_VERSION,
+		});
+	}
+	for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+		let version = i as u32 + 1;
+		println!("migrating database to schema version {}..", version);
+		let tx = conn.transaction()?;
+		tx.execute_batch(migration)?;
+		migrate_data(&tx, version)?;
+		tx.execute("DELETE FROM schema_version", params![])?;
+		tx.execute("INSERT INTO schema_version VALUES (?)", params![version])?;
+		tx.commit()?;
+	}
+	Ok(())
+}
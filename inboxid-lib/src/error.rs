@@ -0,0 +1,205 @@
+use std::{fmt::Display, io, num::ParseIntError, str::Utf8Error};
+
+use maildir::{MailEntryError, MaildirError};
+use mailparse::MailParseError;
+
+use crate::MaildirID;
+
+pub type Result<T, E = InboxidError> = std::result::Result<T, E>;
+
+/// Errors returned by inboxid.
+#[derive(Debug)]
+pub enum InboxidError {
+	/// missing or invalid configuration
+	Config(String),
+	Io(io::Error),
+	/// TLS setup or handshake failed
+	Tls(String),
+	/// the server certificate does not match the configured pin-sha256
+	PinMismatch {
+		expected: String,
+		actual: String,
+	},
+	/// an IMAP command failed
+	Imap {
+		mailbox: Option<String>,
+		source: imap::Error,
+	},
+	/// a maildir operation failed
+	Maildir {
+		mailbox: Option<String>,
+		id: Option<MaildirID>,
+		source: Box<dyn std::error::Error + Send + Sync>,
+	},
+	Database(rusqlite::Error),
+	/// the database was created by a newer version of inboxid
+	UnsupportedSchema {
+		version: u32,
+		supported: u32,
+	},
+	/// a mail, header or identifier could not be parsed
+	Parse(String),
+	/// the UIDVALIDITY of a mailbox changed, stored UIDs are no longer valid
+	UidValidityChanged {
+		mailbox: String,
+		expected: u32,
+		actual: Option<u32>,
+	},
+	Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl InboxidError {
+	pub fn imap(mailbox: &str, source: imap::Error) -> Self {
+		InboxidError::Imap {
+			mailbox: Some(mailbox.to_owned()),
+			source,
+		}
+	}
+
+	pub fn maildir<E: Into<Box<dyn std::error::Error + Send + Sync>>>(mailbox: &str, id: MaildirID, source: E) -> Self {
+		InboxidError::Maildir {
+			mailbox: Some(mailbox.to_owned()),
+			id: Some(id),
+			source: source.into(),
+		}
+	}
+
+	/// Attach the mailbox to an IMAP error.
+	pub fn in_mailbox(self, mailbox: &str) -> Self {
+		match self {
+			InboxidError::Imap { mailbox: None, source } => InboxidError::imap(mailbox, source),
+			e => e
+		}
+	}
+
+	/// Whether the error was caused by a dropped or timed out connection.
+	pub fn is_connection_lost(&self) -> bool {
+		matches!(self,
+			InboxidError::Io(_) |
+			InboxidError::Imap { source: imap::Error::Io(_), .. } |
+			InboxidError::Imap { source: imap::Error::ConnectionLost, .. })
+	}
+}
+
+impl Display for InboxidError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			InboxidError::Config(x) => write!(f, "configuration error: {}", x),
+			InboxidError::Io(e) => write!(f, "I/O error: {}", e),
+			InboxidError::Tls(x) => write!(f, "TLS error: {}", x),
+			InboxidError::PinMismatch { expected, actual } => write!(f, "server certificate does not match pin-sha256 (expected {}, got {})", expected, actual),
+			InboxidError::Imap { mailbox: Some(mailbox), source } => write!(f, "IMAP error in {}: {}", mailbox, source),
+			InboxidError::Imap { mailbox: None, source } => write!(f, "IMAP error: {}", source),
+			InboxidError::Maildir { mailbox, id, source } => {
+				write!(f, "maildir error")?;
+				if let Some(mailbox) = mailbox {
+					write!(f, " in {}", mailbox)?;
+				}
+				if let Some(id) = id {
+					write!(f, " (mail {})", id)?;
+				}
+				write!(f, ": {}", source)
+			},
+			InboxidError::Database(e) => write!(f, "database error: {}", e),
+			InboxidError::UnsupportedSchema { version, supported } => write!(f, "database schema version {} is newer than the supported version {}, please upgrade inboxid", version, supported),
+			InboxidError::Parse(x) => write!(f, "parse error: {}", x),
+			InboxidError::UidValidityChanged { mailbox, expected, actual } => write!(f, "UIDVALIDITY of {} changed (expected {}, got {:?})", mailbox, expected, actual),
+			InboxidError::Other(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl std::error::Error for InboxidError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			InboxidError::Io(e) => Some(e),
+			InboxidError::Imap { source, .. } => Some(source),
+			InboxidError::Maildir { source, .. } => Some(&**source),
+			InboxidError::Database(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for InboxidError {
+	fn from(e: io::Error) -> Self {
+		InboxidError::Io(e)
+	}
+}
+
+impl From<imap::Error> for InboxidError {
+	fn from(source: imap::Error) -> Self {
+		InboxidError::Imap {
+			mailbox: None,
+			source,
+		}
+	}
+}
+
+impl From<MaildirError> for InboxidError {
+	fn from(e: MaildirError) -> Self {
+		InboxidError::Maildir {
+			mailbox: None,
+			id: None,
+			source: e.into(),
+		}
+	}
+}
+
+impl From<MailEntryError> for InboxidError {
+	fn from(e: MailEntryError) -> Self {
+		InboxidError::Maildir {
+			mailbox: None,
+			id: None,
+			source: e.into(),
+		}
+	}
+}
+
+impl From<rusqlite::Error> for InboxidError {
+	fn from(e: rusqlite::Error) -> Self {
+		InboxidError::Database(e)
+	}
+}
+
+impl From<MailParseError> for InboxidError {
+	fn from(e: MailParseError) -> Self {
+		InboxidError::Parse(e.to_string())
+	}
+}
+
+impl From<ParseIntError> for InboxidError {
+	fn from(e: ParseIntError) -> Self {
+		InboxidError::Parse(e.to_string())
+	}
+}
+
+impl From<Utf8Error> for InboxidError {
+	fn from(e: Utf8Error) -> Self {
+		InboxidError::Parse(e.to_string())
+	}
+}
+
+impl From<toml::de::Error> for InboxidError {
+	fn from(e: toml::de::Error) -> Self {
+		InboxidError::Config(e.to_string())
+	}
+}
+
+impl From<toml::ser::Error> for InboxidError {
+	fn from(e: toml::ser::Error) -> Self {
+		InboxidError::Config(e.to_string())
+	}
+}
+
+impl From<subprocess::PopenError> for InboxidError {
+	fn from(e: subprocess::PopenError) -> Self {
+		InboxidError::Other(e.into())
+	}
+}
+
+impl From<anyhow::Error> for InboxidError {
+	fn from(e: anyhow::Error) -> Self {
+		InboxidError::Other(e.into())
+	}
+}
@@ -0,0 +1,249 @@
+use std::{collections::{BTreeMap, HashMap}, convert::TryFrom, fmt::Display, fs, os::unix::fs::MetadataExt, path::{Path, PathBuf}};
+
+use mailparse::{parse_headers, parse_mail};
+use maildir::MailEntry;
+use rusqlite::{Connection, params};
+
+use crate::{Account, HeadersExtension, MailSummary, MaildirID, Result, harvest_addresses, index_mail, maildir_cp, save_summary, unindex_mail};
+
+/// Mailbox used to keep removed mail.
+pub const GONE: &str = ".gone";
+
+/// An inconsistency between the database, the maildir and the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+	/// the mail table references a mail without file
+	MissingFile {
+		mailbox: String,
+		id: MaildirID,
+	},
+	/// a mail file is not in the mail table
+	MissingRow {
+		mailbox: String,
+		id: MaildirID,
+		path: PathBuf,
+		flags: String,
+	},
+	/// the flags in the filename differ from the mail table
+	FlagMismatch {
+		mailbox: String,
+		id: MaildirID,
+		db: String,
+		file: String,
+	},
+	/// several files have the same UID (the first one is kept)
+	DuplicateFiles {
+		mailbox: String,
+		id: MaildirID,
+		extra: Vec<PathBuf>,
+	},
+	/// the mail was stored with another UIDVALIDITY than the current one of the server
+	UidValidityMismatch {
+		mailbox: String,
+		id: MaildirID,
+		server: u32,
+	},
+	/// a mail in `.gone` is still stored in a mailbox (its removal was interrupted)
+	GoneOrphan {
+		mailbox: String,
+		id: MaildirID,
+		path: PathBuf,
+	},
+}
+
+use Problem::*;
+
+impl Display for Problem {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			MissingFile { mailbox, id } => write!(f, "{}/{}: in database, but the file is missing", mailbox, id),
+			MissingRow { mailbox, id, .. } => write!(f, "{}/{}: file is not in database", mailbox, id),
+			FlagMismatch { mailbox, id, db, file } => write!(f, "{}/{}: flags of file ({:?}) differ from database ({:?})", mailbox, id, file, db),
+			DuplicateFiles { mailbox, id, extra } => write!(f, "{}/{}: {} duplicate file(s)", mailbox, id, extra.len()),
+			UidValidityMismatch { mailbox, id, server } => write!(f, "{}/{}: UIDVALIDITY differs from server ({})", mailbox, id, server),
+			GoneOrphan { mailbox, id, .. } => write!(f, "{}/{}: still stored in {}", GONE, id, mailbox),
+		}
+	}
+}
+
+impl Problem {
+	/// What [Problem::repair] does.
+	pub fn repair_description(&self) -> &'static str {
+		match self {
+			MissingFile { .. } => "remove from database (sync fetches it again)",
+			MissingRow { .. } => "add to database",
+			FlagMismatch { .. } => "use the flags of the file",
+			DuplicateFiles { .. } => "delete the duplicates",
+			UidValidityMismatch { .. } => "move to .gone (sync fetches it again)",
+			GoneOrphan { .. } => "delete the copy in .gone",
+		}
+	}
+
+	pub fn repair(&self, conn: &Connection, account: &Account) -> Result<()> {
+		match self {
+			MissingFile { mailbox, id } => {
+				delete_row(conn, mailbox, *id)?;
+			},
+			MissingRow { mailbox, id, path, flags } => {
+				let data = fs::read(path)?;
+				let mail = parse_mail(&data)?;
+				let message_id = mail.get_headers().message_id(mailbox, *id);
+				conn.execute("INSERT INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)",
+					params![mailbox, id.uid_validity, id.uid, message_id, flags])?;
+				let summary = MailSummary::new(mailbox, *id, flags, &mail, data.len() as u64, Some(path.as_path()));
+				save_summary(conn, mailbox, &summary)?;
+				harvest_addresses(conn, &summary, &mail)?;
+				index_mail(conn, mailbox, *id, &mail)?;
+			},
+			FlagMismatch { mailbox, id, file, .. } => {
+				conn.execute("UPDATE mail SET flags = ? WHERE mailbox = ? AND uid_validity = ? AND uid = ?",
+					params![file, mailbox, id.uid_validity, id.uid])?;
+			},
+			DuplicateFiles { extra, .. } => {
+				for path in extra {
+					fs::remove_file(path)?;
+				}
+			},
+			UidValidityMismatch { mailbox, id, .. } => {
+				let maildir = account.get_maildir(mailbox)?;
+				let name = id.to_string();
+				if maildir.find_filename(&name).is_some() {
+					maildir_cp(&maildir, &account.get_maildir(GONE)?, &name, &name, "", true)?;
+					maildir.delete(&name)?;
+				}
+				delete_row(conn, mailbox, *id)?;
+			},
+			GoneOrphan { path, .. } => {
+				fs::remove_file(path)?;
+			},
+		}
+		Ok(())
+	}
+}
+
+fn delete_row(conn: &Connection, mailbox: &str, id: MaildirID) -> Result<()> {
+	conn.execute("DELETE FROM mail WHERE mailbox = ? AND uid_validity = ? AND uid = ?", params![mailbox, id.uid_validity, id.uid])?;
+	unindex_mail(conn, mailbox, id)?;
+	Ok(())
+}
+
+/// Get the files of a maildir (`cur` and `new`) by ID.
+/// Files not named by inboxid are ignored.
+fn mail_files(account: &Account, mailbox: &str) -> Result<BTreeMap<MaildirID, Vec<(PathBuf, String)>>> {
+	let maildir = account.get_maildir(mailbox)?;
+	let mut files: BTreeMap<_, Vec<_>> = BTreeMap::new();
+	let mut add = |entry: MailEntry| {
+		if let Ok(id) = MaildirID::try_from(entry.id()) {
+			files.entry(id).or_default().push((entry.path().to_owned(), entry.flags().to_owned()));
+		}
+	};
+	for entry in maildir.list_cur() {
+		add(entry?);
+	}
+	for entry in maildir.list_new() {
+		add(entry?);
+	}
+	Ok(files)
+}
+
+/// Compare the mail table with the files of a mailbox.
+/// `uid_validity` is the UIDVALIDITY of the mailbox on the server, if known.
+pub fn check_mailbox(conn: &Connection, account: &Account, mailbox: &str, uid_validity: Option<u32>) -> Result<Vec<Problem>> {
+	let files = mail_files(account, mailbox)?;
+	let mut stmt = conn.prepare("SELECT uid_validity, uid, flags FROM mail WHERE mailbox = ?")?;
+	let rows = stmt.query_map(params![mailbox], |row| Ok((MaildirID::from_row(row, 0)?, row.get::<_, String>(2)?)))?
+		.collect::<rusqlite::Result<HashMap<_, _>>>()?;
+
+	let mut problems = Vec::new();
+	let mut ids = rows.keys().chain(files.keys()).copied().collect::<Vec<_>>();
+	ids.sort_unstable();
+	ids.dedup();
+	for id in ids {
+		if let Some(server) = uid_validity.filter(|&x| x != id.uid_validity) {
+			problems.push(UidValidityMismatch { mailbox: mailbox.to_owned(), id, server });
+			continue;
+		}
+		match (rows.get(&id), files.get(&id)) {
+			(Some(_), None) => problems.push(MissingFile { mailbox: mailbox.to_owned(), id }),
+			(None, Some(files)) => problems.push(MissingRow {
+				mailbox: mailbox.to_owned(),
+				id,
+				path: files[0].0.clone(),
+				flags: files[0].1.clone(),
+			}),
+			(Some(db), Some(files)) if !same_flags(db, &files[0].1) => problems.push(FlagMismatch {
+				mailbox: mailbox.to_owned(),
+				id,
+				db: db.clone(),
+				file: files[0].1.clone(),
+			}),
+			_ => {}
+		}
+		if let Some(files) = files.get(&id).filter(|x| x.len() > 1) {
+			problems.push(DuplicateFiles {
+				mailbox: mailbox.to_owned(),
+				id,
+				extra: files[1..].iter().map(|x| x.0.clone()).collect(),
+			});
+		}
+	}
+	Ok(problems)
+}
+
+/// Find mails in `.gone` that are still stored in their mailbox.
+/// The same ID may be used by several mailboxes, so only a hardlink of the stored
+/// file with the same Message-ID is reported (and nothing else is deleted on repair).
+pub fn check_gone(conn: &Connection, account: &Account) -> Result<Vec<Problem>> {
+	let mut problems = Vec::new();
+	for (id, files) in mail_files(account, GONE)? {
+		let mut stmt = conn.prepare_cached("SELECT mailbox, message_id FROM mail WHERE uid_validity = ? AND uid = ?")?;
+		let rows = stmt.query_map(params![id.uid_validity, id.uid], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+			.collect::<rusqlite::Result<Vec<_>>>()?;
+		if rows.is_empty() {
+			continue;
+		}
+		for (path, _) in files {
+			let data = fs::read(&path)?;
+			let headers = match parse_headers(&data) {
+				Ok((x, _)) => x,
+				Err(_) => continue
+			};
+			let mut mailboxes = Vec::new();
+			for (mailbox, message_id) in &rows {
+				if headers.message_id(mailbox, id) != *message_id {
+					continue;
+				}
+				if let Some(entry) = account.get_maildir(mailbox)?.find(&id.to_string()) {
+					if same_file(&path, entry.path())? {
+						mailboxes.push(mailbox.clone());
+					}
+				}
+			}
+			// ambiguous matches are left alone
+			if mailboxes.len() == 1 {
+				problems.push(GoneOrphan {
+					mailbox: mailboxes.remove(0),
+					id,
+					path,
+				});
+			}
+		}
+	}
+	Ok(problems)
+}
+
+/// Whether both paths are links to the same file.
+fn same_file(a: &Path, b: &Path) -> Result<bool> {
+	let (a, b) = (fs::metadata(a)?, fs::metadata(b)?);
+	Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+fn same_flags(a: &str, b: &str) -> bool {
+	let mut a = a.chars().collect::<Vec<_>>();
+	let mut b = b.chars().collect::<Vec<_>>();
+	a.sort_unstable();
+	a.dedup();
+	b.sort_unstable();
+	b.dedup();
+	a == b
+}
@@ -0,0 +1,82 @@
+use std::{collections::BTreeMap, io};
+
+use maildir::Maildir;
+
+use crate::{MaildirExtension, Result};
+
+/// File of the keyword table in a maildir, compatible with Dovecot.
+pub const KEYWORDS_FILE: &str = "dovecot-keywords";
+
+/// IMAP keywords of the mails in a maildir, stored as lowercase letters in the filenames.
+/// Letter `a` is keyword 0, so at most 26 keywords can be stored per maildir.
+/// The table is saved in [KEYWORDS_FILE] as `<index> <keyword>` lines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeywordTable {
+	keywords: BTreeMap<usize, String>,
+	modified: bool,
+}
+
+impl KeywordTable {
+	/// Read the table of a maildir, empty if it does not exist yet.
+	/// Invalid lines are ignored.
+	pub fn load(maildir: &Maildir) -> Result<Self> {
+		let content = match maildir.get_file(KEYWORDS_FILE) {
+			Ok(x) => x,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+			Err(e) => return Err(e.into())
+		};
+		let mut keywords = BTreeMap::new();
+		for line in content.lines() {
+			let mut parts = line.splitn(2, ' ');
+			if let (Some(Ok(idx)), Some(keyword)) = (parts.next().map(str::parse::<usize>), parts.next()) {
+				if idx < 26 && !keyword.is_empty() {
+					keywords.insert(idx, keyword.to_owned());
+				}
+			}
+		}
+		Ok(Self { keywords, modified: false })
+	}
+
+	/// Write the table, if letters were added since it was loaded.
+	pub fn save(&mut self, maildir: &Maildir) -> Result<()> {
+		if self.modified {
+			let content = self.keywords.iter().map(|(idx, keyword)| format!("{} {}\n", idx, keyword)).collect::<String>();
+			maildir.save_file(KEYWORDS_FILE, &content)?;
+			self.modified = false;
+		}
+		Ok(())
+	}
+
+	/// Get the keywords of maildir flags, sorted. Letters without keyword are ignored.
+	pub fn keywords(&self, flags: &str) -> Vec<String> {
+		let mut keywords = flags.chars()
+			.filter(char::is_ascii_lowercase)
+			.filter_map(|c| self.keywords.get(&((c as u8 - b'a') as usize)).cloned())
+			.collect::<Vec<_>>();
+		keywords.sort_unstable();
+		keywords.dedup();
+		keywords
+	}
+
+	/// Get the letters of keywords, adding new keywords to the table.
+	/// Keywords are skipped if all letters are taken.
+	pub fn letters(&mut self, keywords: &[String]) -> String {
+		let mut letters = String::new();
+		for keyword in keywords {
+			// keywords are case-insensitive
+			let idx = match self.keywords.iter().find(|x| x.1.eq_ignore_ascii_case(keyword)) {
+				Some((&idx, _)) => idx,
+				None => match (0..26).find(|x| !self.keywords.contains_key(x)) {
+					Some(idx) => {
+						self.keywords.insert(idx, keyword.clone());
+						self.modified = true;
+						idx
+					},
+					None => continue
+				}
+			};
+			letters.push((b'a' + idx as u8) as char);
+		}
+		letters
+	}
+}
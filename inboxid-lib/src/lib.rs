@@ -1,25 +1,50 @@
-use std::{borrow::Cow, convert::{TryFrom, TryInto}, env, fmt::{Debug, Display}, fs, hash::Hash, io, net::TcpStream, ops::Deref, path::PathBuf};
+use std::{borrow::Cow, collections::BTreeMap, convert::{TryFrom, TryInto}, env, fmt::{Debug, Display}, fs, hash::Hash, io::{self, BufRead, BufReader, Read, Write}, iter, net::{TcpStream, ToSocketAddrs}, os::unix::fs::PermissionsExt, path::{Path, PathBuf}, num::ParseIntError, str::FromStr, time::Duration};
 
-use anyhow::{anyhow, Context};
+use itertools::Itertools;
 use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
 use cursive::{theme::{BaseColor, Color, ColorStyle, ColorType, Effect, Style}, utils::span::{IndexedCow, IndexedSpan, SpannedString}};
 use cursive_tree_view::TreeEntry;
 use directories_next::ProjectDirs;
-use imap::{Session, types::Flag};
+use imap::{Session, extensions::idle::SetReadTimeout, types::Flag};
 use log::info;
-use maildir::{MailEntry, Maildir};
-use mailparse::{MailHeaderMap, ParsedMail, SingleInfo, addrparse, dateparse};
+use maildir::{MailEntries, MailEntry, Maildir};
+use mailparse::{MailHeaderMap, ParsedMail, SingleInfo, parse_headers, parse_mail};
 use once_cell::sync::OnceCell;
 use parking_lot::RwLock;
 use petgraph::{Graph, graph::NodeIndex};
-use rusqlite::{Connection, ToSql, params, types::{FromSql, ToSqlOutput}};
-use rustls_connector::{RustlsConnector, rustls::{ClientSession, StreamOwned}};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rusqlite::{Connection, Row};
+use ring::digest;
+use rustls_connector::{RustlsConnector, rustls::{ClientConfig, ClientSession, Session as _, StreamOwned}};
 use serde::{Deserializer, Serializer};
+use subprocess::{Exec, Redirection};
 use serde::de::Visitor;
 use serde_derive::{Deserialize, Serialize};
 
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-pub type ImapSession = Session<StreamOwned<ClientSession, TcpStream>>;
+mod error;
+pub use error::*;
+mod session;
+pub use session::*;
+mod db;
+pub use db::*;
+mod search;
+pub use search::*;
+mod summary;
+pub use summary::*;
+mod threads;
+pub use threads::*;
+mod addresses;
+pub use addresses::*;
+mod tags;
+pub use tags::*;
+mod fsck;
+pub use fsck::*;
+mod mailbox;
+pub use mailbox::*;
+mod keywords;
+pub use keywords::*;
+
+pub type ImapSession = Session<MailStream>;
 
 pub const UNREAD: char = 'U';
 pub const TRASHED: char = 'T';
@@ -27,15 +52,96 @@ pub const DELETE: char = 'E'; // Exterminate
 pub const SEEN: char = 'S';
 pub const REPLIED: char = 'R';
 pub const FLAGGED: char = 'F';
+pub const DRAFT: char = 'D';
+pub const PASSED: char = 'P';
+/// Maildir flags synced with the server.
+pub const SYNCED_FLAGS: &[char] = &[SEEN, REPLIED, FLAGGED, DRAFT, PASSED];
+/// IMAP keyword of forwarded mail, stored as [PASSED].
+pub const FORWARDED: &str = "$Forwarded";
+
+/// Transport security of the IMAP connection.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Security {
+	/// TLS right from the start (IMAPS)
+	Tls,
+	/// plaintext connection upgraded using STARTTLS
+	Starttls,
+	/// no encryption at all, only use this for local test servers!
+	Plain,
+}
 
-pub fn connect(host: &str, port: u16, user: &str, password: &str) -> Result<ImapSession> {
-	println!("connecting..");
-	let stream = TcpStream::connect((host, port)).context("TCP connect failed")?;
-	let tls = RustlsConnector::new_with_native_certs().context("TLS configuration failed")?;
-	println!("initializing TLS..");
-	let tlsstream = tls.connect(host, stream).context("TLS connection failed")?;
-	println!("initializing client..");
-	let client = imap::Client::new(tlsstream);
+impl Security {
+	pub fn default_port(self) -> u16 {
+		match self {
+			Security::Tls => 993,
+			Security::Starttls | Security::Plain => 143,
+		}
+	}
+}
+
+impl Default for Security {
+	fn default() -> Self {
+		Security::Tls
+	}
+}
+
+impl FromStr for Security {
+	type Err = InboxidError;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"tls" => Ok(Security::Tls),
+			"starttls" => Ok(Security::Starttls),
+			"plain" => Ok(Security::Plain),
+			_ => Err(InboxidError::Config(format!("invalid security mode {:?} (expected tls, starttls or plain)", s)))
+		}
+	}
+}
+
+/// The underlying stream of an [ImapSession].
+pub enum MailStream {
+	Tls(StreamOwned<ClientSession, TcpStream>),
+	Plain(TcpStream),
+}
+
+impl Read for MailStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match self {
+			MailStream::Tls(x) => x.read(buf),
+			MailStream::Plain(x) => x.read(buf),
+		}
+	}
+}
+
+impl Write for MailStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match self {
+			MailStream::Tls(x) => x.write(buf),
+			MailStream::Plain(x) => x.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		match self {
+			MailStream::Tls(x) => x.flush(),
+			MailStream::Plain(x) => x.flush(),
+		}
+	}
+}
+
+impl SetReadTimeout for MailStream {
+	fn set_read_timeout(&mut self, timeout: Option<Duration>) -> imap::error::Result<()> {
+		match self {
+			MailStream::Tls(x) => x.sock.set_read_timeout(timeout),
+			MailStream::Plain(x) => x.set_read_timeout(timeout),
+		}.map_err(imap::Error::Io)
+	}
+}
+
+/// Connect to the server and log in using a password.
+pub fn connect(host: &str, port: u16, security: Security, tls: &TlsOptions, timeouts: Timeouts, user: &str, password: &str) -> Result<ImapSession> {
+	let client = open_client(host, port, security, tls, timeouts)?;
 
 	// the client we have here is unauthenticated.
 	// to do anything useful with the e-mails, we need to log in
@@ -43,42 +149,263 @@ pub fn connect(host: &str, port: u16, user: &str, password: &str) -> Result<Imap
 	Ok(client.login(user, password).map_err(|e| e.0)?)
 }
 
-pub fn get_maildirs() -> Result<Vec<String>> {
-	let maildir = env::var("MAILDIR").expect("missing envvar MAILDIR");
-	let mut dirs = vec![];
-	for dir in fs::read_dir(&maildir)? {
-		let dir = dir?;
-		if dir.file_type()?.is_dir() {
-			let name = dir.file_name().into_string().map_err(|_| anyhow!("failed to decode directory name"))?;
-			if !name.starts_with('.') {
-				dirs.push(name);
+/// Open an unauthenticated connection to the server.
+pub fn open_client(host: &str, port: u16, security: Security, tls: &TlsOptions, timeouts: Timeouts) -> Result<imap::Client<MailStream>> {
+	println!("connecting..");
+	let mut stream = connect_tcp(host, port, timeouts.connect)?;
+	// a hung connection is reported as an I/O error instead of blocking forever
+	stream.set_read_timeout(Some(timeouts.read))?;
+	stream.set_write_timeout(Some(timeouts.read))?;
+	let stream = if security == Security::Plain {
+		MailStream::Plain(stream)
+	} else {
+		if security == Security::Starttls {
+			println!("requesting STARTTLS..");
+			starttls(&mut stream)?;
+		}
+		let connector = tls.connector()?;
+		println!("initializing TLS..");
+		let stream = connector.connect(host, stream).map_err(|e| InboxidError::Tls(format!("TLS connection failed: {}", e)))?;
+		if let Some(pin) = tls.pin_sha256.as_ref() {
+			check_pin(&stream.sess, pin)?;
+		}
+		MailStream::Tls(stream)
+	};
+	println!("initializing client..");
+	Ok(imap::Client::new(stream))
+}
+
+/// Connect to the first reachable address of the host.
+fn connect_tcp(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+	let mut last_error = None;
+	for addr in (host, port).to_socket_addrs()? {
+		match TcpStream::connect_timeout(&addr, timeout) {
+			Ok(stream) => return Ok(stream),
+			Err(e) => last_error = Some(e)
+		}
+	}
+	Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no address found for {}", host))))
+}
+
+/// Network timeouts of the IMAP connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+	/// time to wait for the server to accept the connection
+	pub connect: Duration,
+	/// time to wait for every read or write, afterwards the connection is considered lost
+	pub read: Duration,
+}
+
+impl Default for Timeouts {
+	fn default() -> Self {
+		Self {
+			connect: Duration::from_secs(30),
+			read: Duration::from_secs(120),
+		}
+	}
+}
+
+/// Additional TLS settings of an account:
+///
+/// ```toml
+/// [accounts.work.tls]
+/// ca-file = "/etc/ssl/our-internal-ca.pem"
+/// pin-sha256 = "9f:86:d0:81:88:4c:7d:65:9a:2f:ea:a0:c5:5a:d0:15:a3:bf:4f:1b:2b:0b:82:2c:d1:5d:6c:15:b0:f0:0a:08"
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct TlsOptions {
+	/// PEM file with additional trusted CA certificates
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ca_file: Option<PathBuf>,
+	/// SHA-256 fingerprint of the server certificate (hex, colons are optional)
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub pin_sha256: Option<String>,
+}
+
+impl TlsOptions {
+	fn connector(&self) -> Result<RustlsConnector> {
+		let mut config = ClientConfig::new();
+		config.root_store = rustls_native_certs::load_native_certs()
+			.or_else(|(partial, e)| partial.ok_or(e))?;
+		if let Some(ca_file) = self.ca_file.as_ref() {
+			let file = fs::File::open(ca_file).map_err(|e| InboxidError::Config(format!("failed to open ca-file {:?}: {}", ca_file, e)))?;
+			let (valid, _) = config.root_store.add_pem_file(&mut BufReader::new(file))
+				.map_err(|_| InboxidError::Config(format!("failed to parse ca-file {:?}", ca_file)))?;
+			if valid == 0 {
+				return Err(InboxidError::Config(format!("no usable certificates in ca-file {:?}", ca_file)));
 			}
 		}
+		Ok(config.into())
+	}
+
+	fn validate(&self) -> Result<()> {
+		if let Some(pin) = self.pin_sha256.as_ref() {
+			parse_pin(pin)?;
+		}
+		Ok(())
 	}
-	Ok(dirs)
 }
 
-pub fn get_maildir(mailbox: &str) -> Result<Maildir> {
-	let maildir = env::var("MAILDIR").expect("missing envvar MAILDIR");
-	let maildir = format!("{}/{}", maildir, mailbox);
-	let maildir = Maildir::from(maildir);
-	maildir.create_dirs()?;
-	Ok(maildir)
+/// Normalize a pinned fingerprint to 64 lowercase hex digits.
+fn parse_pin(pin: &str) -> Result<String> {
+	let hex = pin.replace(':', "").to_ascii_lowercase();
+	if hex.len() != 64 || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+		return Err(InboxidError::Config(format!("invalid pin-sha256 {:?} (expected 64 hex digits)", pin)));
+	}
+	Ok(hex)
 }
 
-pub fn get_db() -> Result<Connection> {
-	let db = env::var("MAILDB").expect("missing envvar MAILDB");
-	let conn = Connection::open(&db)?;
+fn check_pin(session: &ClientSession, pin: &str) -> Result<()> {
+	let expected = parse_pin(pin)?;
+	let cert = session.get_peer_certificates().unwrap_or_default().into_iter().next()
+		.ok_or_else(|| InboxidError::Tls("server did not present a certificate".to_owned()))?;
+	let actual = digest::digest(&digest::SHA256, &cert.0).as_ref().iter().map(|x| format!("{:02x}", x)).join("");
+	if actual != expected {
+		return Err(InboxidError::PinMismatch {
+			expected,
+			actual
+		});
+	}
+	Ok(())
+}
+
+/// Authentication method used to log in.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Auth {
+	/// plain LOGIN using the account password
+	Login,
+	/// SASL XOAUTH2 using a token
+	Xoauth2,
+	/// SASL OAUTHBEARER (RFC 7628) using a token
+	Oauthbearer,
+}
+
+impl Default for Auth {
+	fn default() -> Self {
+		Auth::Login
+	}
+}
+
+impl FromStr for Auth {
+	type Err = InboxidError;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"login" => Ok(Auth::Login),
+			"xoauth2" => Ok(Auth::Xoauth2),
+			"oauthbearer" => Ok(Auth::Oauthbearer),
+			_ => Err(InboxidError::Config(format!("invalid auth method {:?} (expected login, xoauth2 or oauthbearer)", s)))
+		}
+	}
+}
 
-	conn.execute("
-	CREATE TABLE IF NOT EXISTS mail(
-		mailbox STRING NOT NULL,
-		uid INTEGER NOT NULL,
-		message_id STRING NOT NULL,
-		flags STRING NOT NULL
-	)", params![])?;
+/// Side that wins if a flag was changed locally and on the server since the last sync.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictWinner {
+	Local,
+	Remote,
+}
 
-	Ok(conn)
+impl Default for ConflictWinner {
+	fn default() -> Self {
+		ConflictWinner::Local
+	}
+}
+
+impl FromStr for ConflictWinner {
+	type Err = InboxidError;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"local" => Ok(ConflictWinner::Local),
+			"remote" => Ok(ConflictWinner::Remote),
+			_ => Err(InboxidError::Config(format!("invalid conflict winner {:?} (expected local or remote)", s)))
+		}
+	}
+}
+
+struct OAuth2<'a> {
+	account: &'a Account,
+	token: String,
+}
+
+impl imap::Authenticator for OAuth2<'_> {
+	type Response = String;
+
+	fn process(&self, challenge: &[u8]) -> Self::Response {
+		if !challenge.is_empty() {
+			// the server rejected the token and describes the error,
+			// an empty response finishes the exchange
+			return String::new();
+		}
+		let account = self.account;
+		match account.auth {
+			Auth::Oauthbearer => format!(
+				"n,a={},\x01host={}\x01port={}\x01auth=Bearer {}\x01\x01",
+				account.user, account.host, account.port(), self.token
+			),
+			_ => format!("user={}\x01auth=Bearer {}\x01\x01", account.user, self.token),
+		}
+	}
+}
+
+/// Parse a numeric envvar, if it is set.
+fn env_number<T: FromStr<Err = ParseIntError>>(var: &str) -> Result<Option<T>> {
+	Ok(env::var(var).ok().map(|x| x.parse()).transpose()?)
+}
+
+/// Run a shell command and return the first line of its output.
+fn run_secret_command(kind: &str, command: &str) -> Result<String> {
+	let output = Exec::shell(command)
+		.stdout(Redirection::Pipe)
+		.capture()
+		.map_err(|e| InboxidError::Config(format!("failed to run {} {:?}: {}", kind, command, e)))?;
+	if !output.success() {
+		return Err(InboxidError::Config(format!("{} {:?} failed: {:?}", kind, command, output.exit_status)));
+	}
+	first_line(&output.stdout_str()).ok_or_else(|| InboxidError::Config(format!("{} {:?} printed nothing", kind, command)))
+}
+
+fn first_line(x: &str) -> Option<String> {
+	x.lines().next().filter(|x| !x.is_empty()).map(ToOwned::to_owned)
+}
+
+/// Upgrade a fresh plaintext connection using STARTTLS.
+/// The server will not repeat its greeting afterwards.
+fn starttls(stream: &mut TcpStream) -> Result<()> {
+	let mut reader = BufReader::new(stream.try_clone()?);
+	let mut line = String::new();
+	reader.read_line(&mut line)?; // greeting
+	stream.write_all(b"a0 STARTTLS\r\n")?;
+	loop {
+		line.clear();
+		if reader.read_line(&mut line)? == 0 {
+			return Err(InboxidError::Tls("connection closed by server before STARTTLS".to_owned()));
+		}
+		if let Some(status) = line.strip_prefix("a0 ") {
+			if status.starts_with("OK") {
+				return Ok(());
+			}
+			return Err(InboxidError::Tls(format!("server refused STARTTLS: {}", status.trim_end())));
+		}
+	}
+}
+
+pub fn get_maildirs() -> Result<Vec<String>> {
+	account()?.get_maildirs()
+}
+
+pub fn get_maildir(mailbox: &str) -> Result<Maildir> {
+	account()?.get_maildir(mailbox)
+}
+
+pub fn get_db() -> Result<Connection> {
+	account()?.get_db()
 }
 
 pub fn gen_id(uid_validity: u32, uid: u32) -> String {
@@ -92,12 +419,13 @@ pub struct MaildirID {
 }
 
 impl TryFrom<&str> for MaildirID {
-	type Error = Box<dyn std::error::Error>;
+	type Error = InboxidError;
 
 	fn try_from(id: &str) -> Result<Self> {
+		let invalid = || InboxidError::Parse(format!("invalid ID {:?}", id));
 		let mut parts = id.splitn(2, '_');
-		let uid_validity = parts.next().context("invalid ID")?.parse()?;
-		let uid = parts.next().context("invalid ID")?.parse()?;
+		let uid_validity = parts.next().ok_or_else(invalid)?.parse()?;
+		let uid = parts.next().ok_or_else(invalid)?.parse()?;
 		Ok(MaildirID {
 			uid_validity,
 			uid,
@@ -105,26 +433,6 @@ impl TryFrom<&str> for MaildirID {
 	}
 }
 
-impl From<i64> for MaildirID {
-	fn from(x: i64) -> Self {
-		let x = load_i64(x);
-		Self::new((x >> 32) as u32, ((x << 32) >> 32) as u32)
-	}
-}
-
-impl ToSql for MaildirID {
-    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'static>> {
-        Ok(ToSqlOutput::from(self.to_i64()))
-    }
-}
-
-impl FromSql for MaildirID {
-    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
-        let x = i64::column_result(value)?;
-		Ok(MaildirID::from(x))
-    }
-}
-
 impl Display for MaildirID {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		write!(f, "{}_{}", self.uid_validity, self.uid)
@@ -139,12 +447,14 @@ impl MaildirID {
 		}
 	}
 
-	pub fn to_u64(&self) -> u64 {
-		((self.uid_validity as u64) << 32) | self.uid as u64
+	/// Read an ID stored in the `uid_validity` and `uid` columns,
+	/// starting at column `idx`.
+	pub fn from_row(row: &Row, idx: usize) -> rusqlite::Result<Self> {
+		Ok(Self::new(row.get(idx)?, row.get(idx + 1)?))
 	}
 
-	pub fn to_i64(&self) -> i64 {
-		store_i64(self.to_u64())
+	pub fn to_u64(&self) -> u64 {
+		((self.uid_validity as u64) << 32) | self.uid as u64
 	}
 
 	pub fn to_imap(&self) -> String {
@@ -153,7 +463,11 @@ impl MaildirID {
 }
 
 pub fn maildir_cp(maildir1: &Maildir, maildir2: &Maildir, id1: &str, id2: &str, flags: &str, new: bool) -> Result<()> {
-	let name = maildir1.find_filename(id1).context("mail not found")?;
+	let name = maildir1.find_filename(id1).ok_or_else(|| InboxidError::Maildir {
+		mailbox: None,
+		id: id1.try_into().ok(),
+		source: "mail not found".into()
+	})?;
 	if new {
 		maildir2.store_new_from_path(id2, name)?;
 	} else {
@@ -162,33 +476,120 @@ pub fn maildir_cp(maildir1: &Maildir, maildir2: &Maildir, id1: &str, id2: &str,
 	Ok(())
 }
 
-pub struct EasyMail<'a> {
-	mail: Option<ParsedMail<'a>>,
+/// A mail with the most important headers extracted.
+/// The content is only read when needed, see [EasyMail::parsed].
+pub struct EasyMail {
+	/// location of the mail file (not known for mails created from a [MailSummary])
+	path: Option<PathBuf>,
+	content: OnceCell<Vec<u8>>,
+	pseudo: bool,
 	pub id: MaildirID,
 	flags: RwLock<String>,
 	from: Option<SingleInfo>,
 	from_raw: String,
 	pub subject: String,
 	pub date: DateTime<Local>,
+	pub date_source: DateSource,
+	/// date for display, marked with `?` if it was not taken from the Date header
 	pub date_iso: String,
+	pub message_id: String,
+	pub in_reply_to: String,
+	pub references: String,
+	/// mailbox of the mail, if it is not the one being listed (threads span mailboxes)
+	pub mailbox: Option<String>,
+	/// whether the mail is stored in `new`
+	pub new: bool,
 }
 
-impl EasyMail<'_> {
+impl EasyMail {
 	pub fn new_pseudo(subject: String) -> Self {
 		Self {
-			mail: None,
+			path: None,
+			content: OnceCell::new(),
+			pseudo: true,
 			id: MaildirID::new(0, 0),
 			flags: "S".to_owned().into(),
 			from: None,
 			from_raw: String::new(),
 			subject,
 			date: Local.from_utc_datetime(&NaiveDateTime::from_timestamp(0, 0)),
-			date_iso: "????-??-??".to_owned()
+			date_source: DateSource::Epoch,
+			date_iso: "????-??-??".to_owned(),
+			message_id: String::new(),
+			in_reply_to: String::new(),
+			references: String::new(),
+			mailbox: None,
+			new: false,
+		}
+	}
+
+	/// Read the headers of a mail file, the body is not read.
+	pub fn from_entry(entry: &MailEntry, new: bool) -> Result<Self> {
+		Self::from_file(entry.path(), MaildirID::try_from(entry.id())?, entry.flags(), new)
+	}
+
+	fn from_file(path: &Path, id: MaildirID, flags: &str, new: bool) -> Result<Self> {
+		let mut file = BufReader::new(fs::File::open(path)?);
+		let mut header = Vec::new();
+		while file.read_until(b'\n', &mut header)? > 0 {
+			if header.ends_with(b"\n\n") || header.ends_with(b"\n\r\n") || header == b"\n" || header == b"\r\n" {
+				break;
+			}
 		}
+		let (headers, _) = parse_headers(&header)?;
+		let from_raw = headers.get_header("From");
+		let from = parse_addresses(&from_raw).into_iter().next();
+		let (date, date_source) = mail_date(&*headers, Some(path));
+		let date = Local.from_utc_datetime(&NaiveDateTime::from_timestamp(date, 0));
+		Ok(Self {
+			path: Some(path.to_owned()),
+			content: OnceCell::new(),
+			pseudo: false,
+			id,
+			flags: flags.to_owned().into(),
+			from,
+			from_raw,
+			subject: headers.get_header("Subject"),
+			date_iso: format_date(&date, date_source),
+			date,
+			date_source,
+			message_id: headers.get_header("Message-ID"),
+			in_reply_to: headers.get_header("In-Reply-To"),
+			references: headers.get_header("References"),
+			mailbox: None,
+			new,
+		})
 	}
 
 	pub fn is_pseudo(&self) -> bool {
-		self.mail.is_none()
+		self.pseudo
+	}
+
+	pub fn path(&self) -> Option<&Path> {
+		self.path.as_deref()
+	}
+
+	/// Whether the content of the mail was read already.
+	pub fn is_loaded(&self) -> bool {
+		self.content.get().is_some()
+	}
+
+	/// Read the mail file. The content is kept until the mail is dropped.
+	pub fn content(&self) -> Result<&[u8]> {
+		let content = self.content.get_or_try_init(|| {
+			let path = self.path.as_ref().ok_or_else(|| InboxidError::Maildir {
+				mailbox: self.mailbox.clone(),
+				id: Some(self.id),
+				source: "location of mail unknown".into()
+			})?;
+			Ok::<_, InboxidError>(fs::read(path)?)
+		})?;
+		Ok(content)
+	}
+
+	/// Parse the whole mail.
+	pub fn parsed(&self) -> Result<ParsedMail<'_>> {
+		Ok(parse_mail(self.content()?)?)
 	}
 
 	pub fn from(&self) -> String {
@@ -249,32 +650,65 @@ impl EasyMail<'_> {
 		self.flags.read().clone()
 	}
 
-	pub fn get_header_values(&self, header: &str) -> Vec<String> {
-		self.get_headers().get_all_values(header)
+	pub fn get_header_values(&self, header: &str) -> Result<Vec<String>> {
+		Ok(self.parsed()?.get_headers().get_all_values(header))
+	}
+}
+
+impl From<MailSummary> for EasyMail {
+	fn from(summary: MailSummary) -> Self {
+		let from = parse_addresses(&summary.from).into_iter().next();
+		let date = Local.from_utc_datetime(&NaiveDateTime::from_timestamp(summary.date, 0));
+		Self {
+			path: None,
+			content: OnceCell::new(),
+			pseudo: false,
+			id: summary.id,
+			flags: summary.flags.into(),
+			from,
+			from_raw: summary.from,
+			subject: summary.subject,
+			date_iso: format_date(&date, summary.date_source),
+			date,
+			date_source: summary.date_source,
+			message_id: summary.message_id,
+			in_reply_to: summary.in_reply_to,
+			references: summary.references,
+			mailbox: None,
+			new: summary.new,
+		}
+	}
+}
+
+fn format_date(date: &DateTime<Local>, source: DateSource) -> String {
+	let mut formatted = date.format("%Y-%m-%d %H:%M").to_string();
+	if !source.is_certain() {
+		formatted.push('?');
 	}
+	formatted
 }
 
-impl Debug for EasyMail<'_> {
+impl Debug for EasyMail {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		write!(f, "Mail[ID={},Subject={:?}]", self.id.uid, self.subject)
 	}
 }
 
-impl Display for EasyMail<'_> {
+impl Display for EasyMail {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		write!(f, "{}", self.subject)
 	}
 }
 
-impl PartialEq for EasyMail<'_> {
+impl PartialEq for EasyMail {
 	fn eq(&self, other: &Self) -> bool {
 		self.id == other.id && self.from == other.from && self.subject == other.subject
 	}
 }
 
-impl Eq for EasyMail<'_> {}
+impl Eq for EasyMail {}
 
-impl Hash for EasyMail<'_> {
+impl Hash for EasyMail {
 	fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
 		self.id.hash(state);
 		if let Some(from) = self.from.as_ref() {
@@ -285,21 +719,16 @@ impl Hash for EasyMail<'_> {
 	}
 }
 
-impl<'a> Deref for EasyMail<'a> {
-	type Target = ParsedMail<'a>;
-
-	fn deref(&self) -> &Self::Target {
-		&self.mail.as_ref().unwrap()
-	}
-}
-
-impl TreeEntry for &EasyMail<'_> {
+impl TreeEntry for &EasyMail {
 	fn display(&self, width: usize) -> SpannedString<Style> {
 		if self.is_pseudo() {
 			return self.subject.clone().into();
 		}
 		let from = self.from();
-		let mut line = self.subject.clone();
+		let mut line = match self.mailbox.as_ref() {
+			Some(mailbox) => format!("[{}] {}", mailbox, self.subject),
+			None => self.subject.clone()
+		};
 		let mut i = width.saturating_sub(1 + from.len() + 1 + self.date_iso.len());
 		while i <= line.len() && !line.is_char_boundary(i) {
 			if i == 0 {
@@ -317,7 +746,7 @@ impl TreeEntry for &EasyMail<'_> {
 		line.push(' ');
 		line += &self.date_iso;
 
-		let style = if self.has_flag2(DELETE) {
+		let mut style = if self.has_flag2(DELETE) {
 			CONFIG.get().unwrap().read().browse.deleted_style
 		} else if self.has_flag(&Flag::Deleted) {
 			CONFIG.get().unwrap().read().browse.trashed_style
@@ -326,6 +755,9 @@ impl TreeEntry for &EasyMail<'_> {
 		} else {
 			Style::default()
 		};
+		if self.new {
+			style = style.combine(CONFIG.get().unwrap().read().browse.recent_style);
+		}
 		let spans = vec![
 			IndexedSpan {
 				content: IndexedCow::Borrowed {
@@ -449,8 +881,8 @@ pub fn fallback_mid(mailbox: &str, id: MaildirID) -> String {
 pub trait MaildirExtension {
 	fn get_file(&self, name: &str) -> std::result::Result<String, io::Error>;
 	fn save_file(&self, name: &str, content: &str) -> std::result::Result<(), io::Error>;
-	fn get_mails<'a>(&self, entries: &'a mut [MailEntry]) -> Result<Vec<EasyMail<'a>>>;
-	fn get_mails2<'a>(&self, entries: &'a mut [&'a mut MailEntry]) -> Result<Vec<EasyMail<'a>>>;
+	fn mails(&self) -> Mails;
+	fn find_mail(&self, id: MaildirID) -> Result<EasyMail>;
 }
 
 impl MaildirExtension for Maildir {
@@ -462,72 +894,69 @@ impl MaildirExtension for Maildir {
 		fs::write(self.path().join(name), content)
 	}
 
-	fn get_mails<'a>(&self, entries: &'a mut [MailEntry]) -> Result<Vec<EasyMail<'a>>> {
-		let mut mails = Vec::new();
-		for maile in entries {
-			let id = maile.id().try_into()?;
-			let flags = maile.flags().to_owned();
-			let mail = maile.parsed()?;
-			let headers = mail.get_headers();
-			let from_raw = headers.get_all_values("From").join(" ");
-			let from = addrparse(&from_raw).map(|x| x.extract_single_info()).ok().flatten();
-			let subject = headers.get_all_values("Subject").join(" ");
-			let date = headers.get_all_values("Date").join(" ");
-			let date = dateparse(&date).map(|x|
-				Local.from_utc_datetime(&NaiveDateTime::from_timestamp(x, 0))
-			)?;
-			mails.push(EasyMail {
-				mail: Some(mail),
-				flags: flags.into(),
-				id,
-				from,
-				from_raw,
-				subject,
-				date_iso: date.format("%Y-%m-%d %H:%M").to_string(),
-				date,
-			});
-		}
-		Ok(mails)
-	}
-
-	// TODO this should be unified with the above
-	fn get_mails2<'a>(&self, entries: &'a mut [&'a mut MailEntry]) -> Result<Vec<EasyMail<'a>>> {
-		let mut mails = Vec::new();
-		for maile in entries {
-			let id = maile.id().try_into()?;
-			let flags = maile.flags().to_owned();
-			let mail = maile.parsed()?;
-			let headers = mail.get_headers();
-			let from_raw = headers.get_all_values("From").join(" ");
-			let from = addrparse(&from_raw).map(|x| x.extract_single_info()).ok().flatten();
-			let subject = headers.get_all_values("Subject").join(" ");
-			let date = headers.get_all_values("Date").join(" ");
-			let date = dateparse(&date).map(|x|
-				Local.from_utc_datetime(&NaiveDateTime::from_timestamp(x, 0))
-			)?;
-			mails.push(EasyMail {
-				mail: Some(mail),
-				flags: flags.into(),
-				id,
-				from,
-				from_raw,
-				subject,
-				date_iso: date.format("%Y-%m-%d %H:%M").to_string(),
-				date,
-			});
-		}
-		Ok(mails)
-	}
-}
-
-#[deprecated]
-pub fn store_i64(x: u64) -> i64 {
-	unsafe { std::mem::transmute(x) }
-}
-
-#[deprecated]
-pub fn load_i64(x: i64) -> u64 {
-	unsafe { std::mem::transmute(x) }
+	/// List the mails in `cur` and `new`, only their headers are read.
+	fn mails(&self) -> Mails {
+		Mails {
+			cur: Some(self.list_cur()),
+			new: self.list_new(),
+		}
+	}
+
+	/// Find a single mail in `cur` or `new`, only its headers are read.
+	fn find_mail(&self, id: MaildirID) -> Result<EasyMail> {
+		let entry = self.find(&id.to_string()).ok_or_else(|| InboxidError::Maildir {
+			mailbox: None,
+			id: Some(id),
+			source: "mail not found".into()
+		})?;
+		let new = entry.path().parent().map_or(false, |x| x.ends_with("new"));
+		EasyMail::from_entry(&entry, new)
+	}
+}
+
+/// Lazy iterator over the mails of a maildir, see [MaildirExtension::mails].
+pub struct Mails {
+	cur: Option<MailEntries>,
+	new: MailEntries,
+}
+
+impl Mails {
+	/// Skip the mails in `cur`.
+	pub fn only_new(mut self) -> Self {
+		self.cur = None;
+		self
+	}
+
+	/// Read the headers of all mails using all cores.
+	/// Unlike iterating, the mails are returned sorted by ID.
+	pub fn collect_parallel(self) -> Result<Vec<EasyMail>> {
+		let mut entries = Vec::new();
+		for (entries_iter, new) in self.cur.into_iter().map(|x| (x, false)).chain(iter::once((self.new, true))) {
+			for entry in entries_iter {
+				let entry = entry?;
+				entries.push((MaildirID::try_from(entry.id())?, new, entry.flags().to_owned(), entry.path().to_owned()));
+			}
+		}
+		entries.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+		entries.par_iter()
+			.map(|(id, new, flags, path)| EasyMail::from_file(path, *id, flags, *new))
+			.collect()
+	}
+}
+
+impl Iterator for Mails {
+	type Item = Result<EasyMail>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if let Some(cur) = self.cur.as_mut() {
+			match cur.next() {
+				Some(entry) => return Some(entry.map_err(InboxidError::from).and_then(|x| EasyMail::from_entry(&x, false))),
+				None => self.cur = None
+			}
+		}
+		let entry = self.new.next()?;
+		Some(entry.map_err(InboxidError::from).and_then(|x| EasyMail::from_entry(&x, true)))
+	}
 }
 
 pub fn remove_cow<'a>(x: &Flag<'a>) -> Flag<'static> {
@@ -544,39 +973,91 @@ pub fn remove_cow<'a>(x: &Flag<'a>) -> Flag<'static> {
 }
 
 pub fn get_imap_session() -> Result<ImapSession> {
-	let host = env::var("MAILHOST").expect("missing envvar MAILHOST");
-	let user = env::var("MAILUSER").expect("missing envvar MAILUSER");
-	let password = env::var("MAILPASSWORD").expect("missing envvar MAILPASSWORD");
-	let port = 993;
-	connect(&host, port, &user, &password)
-}
-
-pub fn load_config() {
-	CONFIG.get_or_init(|| {
-		let config = Config::load_from_fs();
-		let cfg = match config {
-			Ok(config) => if let Some(config) = config {
-				config.into()
-			} else {
-				Config::default().into()
+	account()?.connect()
+}
+
+static ACCOUNT: OnceCell<Account> = OnceCell::new();
+
+/// Get the currently selected account.
+/// If [select_account] was not called, the default account is used.
+pub fn account() -> Result<&'static Account> {
+	ACCOUNT.get_or_try_init(|| get_account(None))
+}
+
+/// Select the account used by [get_maildirs], [get_maildir], [get_db] and [get_imap_session].
+pub fn select_account(name: Option<&str>) -> Result<()> {
+	let account = get_account(name)?;
+	ACCOUNT.set(account).map_err(|_| InboxidError::Config("account already selected".to_owned()))?;
+	Ok(())
+}
+
+/// Look up an account in the configuration file.
+/// Without a name, the `default-account` is used. If no accounts are configured,
+/// the legacy MAILHOST/MAILUSER/MAILDIR/MAILDB envvars are used.
+pub fn get_account(name: Option<&str>) -> Result<Account> {
+	load_config()?;
+	let config = CONFIG.get().unwrap().read();
+	let name = match name.or_else(|| config.default_account.as_deref()) {
+		Some(name) => name,
+		None => match config.accounts.len() {
+			0 => {
+				let account = Account::from_env()?;
+				account.tls.validate()?;
+				return Ok(account);
 			},
-			Err(e) => panic!("failed to load configuration: {:?}", e)
-		};
+			1 => config.accounts.keys().next().unwrap(),
+			_ => return Err(InboxidError::Config("multiple accounts configured, use --account or set default-account".to_owned()))
+		}
+	};
+	let account = config.accounts.get(name).cloned().ok_or_else(|| InboxidError::Config(format!("unknown account {:?}", name)))?;
+	account.tls.validate()?;
+	Ok(account)
+}
+
+/// Collect the command line arguments, handling the `--account <name>` option
+/// shared by all binaries. The option is removed from the returned arguments.
+pub fn get_args() -> Result<Vec<String>> {
+	let mut args = Vec::new();
+	let mut account = None;
+	let mut all_args = env::args();
+	while let Some(arg) = all_args.next() {
+		if arg == "--account" {
+			account = Some(all_args.next().ok_or_else(|| InboxidError::Config("missing account name".to_owned()))?);
+		} else if let Some(name) = arg.strip_prefix("--account=") {
+			account = Some(name.to_owned());
+		} else {
+			args.push(arg);
+		}
+	}
+	select_account(account.as_deref())?;
+	Ok(args)
+}
+
+pub fn load_config() -> Result<()> {
+	CONFIG.get_or_try_init(|| {
+		let cfg: RwLock<Config> = Config::load_from_fs()?.unwrap_or_default().into();
 		info!("config {:?}", cfg);
-		cfg
-	});
+		Ok::<_, InboxidError>(cfg)
+	})?;
+	Ok(())
 }
 
 pub static CONFIG: OnceCell<RwLock<Config>> = OnceCell::new();
 
 #[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
 pub struct Config {
 	#[serde(default)]
-	pub browse: Browse
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub default_account: Option<String>,
+	#[serde(default)]
+	pub browse: Browse,
+	#[serde(default)]
+	pub accounts: BTreeMap<String, Account>,
 }
 
 fn get_paths() -> Result<ProjectDirs> {
-	Ok(directories_next::ProjectDirs::from("", "", "Inboxid").context("unable to determine configuration directory")?)
+	directories_next::ProjectDirs::from("", "", "Inboxid").ok_or_else(|| InboxidError::Config("unable to determine configuration directory".to_owned()))
 }
 
 fn get_config_path() -> Result<PathBuf> {
@@ -606,11 +1087,248 @@ impl Config {
 impl Default for Config {
 	fn default() -> Self {
 		Self {
-			browse: Browse::default()
+			default_account: None,
+			browse: Browse::default(),
+			accounts: BTreeMap::new(),
 		}
 	}
 }
 
+/// A mail account, configured like this:
+///
+/// ```toml
+/// [accounts.work]
+/// host = "imap.example.com"
+/// user = "johndoe@example.com"
+/// password-command = "pass show mail/work"
+/// maildir = "/home/johndoe/mail/work"
+/// db = "/home/johndoe/mail/work/index.db"
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Account {
+	#[serde(default)]
+	pub host: String,
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub port: Option<u16>,
+	#[serde(default)]
+	pub security: Security,
+	#[serde(default)]
+	pub user: String,
+	/// command printing the password on the first line of its output
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub password_command: Option<String>,
+	/// file containing the password on its first line, only readable by the user
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub password_file: Option<PathBuf>,
+	#[serde(default)]
+	pub auth: Auth,
+	/// command printing an OAuth2 access token (used if auth is xoauth2 or oauthbearer)
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub token_command: Option<String>,
+	/// command printing a fresh token, used if the server rejected the first one
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub token_refresh_command: Option<String>,
+	/// mailboxes watched by `inboxid-fetch --idle` in addition to the INBOX
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub idle_mailboxes: Vec<String>,
+	/// seconds to wait for the server to accept the connection (default: 30)
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub connect_timeout: Option<u64>,
+	/// seconds to wait for a response before the connection is considered lost (default: 120)
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub read_timeout: Option<u64>,
+	/// how often idempotent operations are retried after the connection was lost (default: 3)
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub retries: Option<u32>,
+	pub maildir: PathBuf,
+	pub db: PathBuf,
+	#[serde(default)]
+	pub tls: TlsOptions,
+	/// side that wins if a flag was changed locally and on the server (default: local)
+	#[serde(default)]
+	pub conflict_winner: ConflictWinner,
+}
+
+impl Account {
+	/// Construct an account from the MAILHOST, MAILUSER, MAILPORT, MAILSECURITY,
+	/// MAILPASSWORDCOMMAND, MAILPASSWORDFILE, MAILAUTH, MAILTOKENCOMMAND,
+	/// MAILTOKENREFRESHCOMMAND, MAILIDLEMAILBOXES (comma-separated), MAILCONNECTTIMEOUT, MAILREADTIMEOUT,
+	/// MAILRETRIES, MAILCAFILE, MAILPINSHA256, MAILCONFLICTWINNER, MAILDIR and MAILDB envvars.
+	pub fn from_env() -> Result<Self> {
+		let security = match env::var("MAILSECURITY") {
+			Ok(x) => x.parse()?,
+			Err(_) => Security::default()
+		};
+		let auth = match env::var("MAILAUTH") {
+			Ok(x) => x.parse()?,
+			Err(_) => Auth::default()
+		};
+		let conflict_winner = match env::var("MAILCONFLICTWINNER") {
+			Ok(x) => x.parse()?,
+			Err(_) => ConflictWinner::default()
+		};
+		Ok(Self {
+			host: env::var("MAILHOST").unwrap_or_default(),
+			port: env_number("MAILPORT")?,
+			security,
+			user: env::var("MAILUSER").unwrap_or_default(),
+			password_command: env::var("MAILPASSWORDCOMMAND").ok(),
+			password_file: env::var_os("MAILPASSWORDFILE").map(Into::into),
+			auth,
+			token_command: env::var("MAILTOKENCOMMAND").ok(),
+			token_refresh_command: env::var("MAILTOKENREFRESHCOMMAND").ok(),
+			idle_mailboxes: env::var("MAILIDLEMAILBOXES").map(|x| x.split(',').map(|x| x.trim().to_owned()).filter(|x| !x.is_empty()).collect()).unwrap_or_default(),
+			connect_timeout: env_number("MAILCONNECTTIMEOUT")?,
+			read_timeout: env_number("MAILREADTIMEOUT")?,
+			retries: env_number("MAILRETRIES")?,
+			maildir: env::var("MAILDIR").unwrap_or_default().into(),
+			db: env::var("MAILDB").unwrap_or_default().into(),
+			tls: TlsOptions {
+				ca_file: env::var_os("MAILCAFILE").map(Into::into),
+				pin_sha256: env::var("MAILPINSHA256").ok(),
+			},
+			conflict_winner,
+		})
+	}
+
+	pub fn port(&self) -> u16 {
+		self.port.unwrap_or_else(|| self.security.default_port())
+	}
+
+	pub fn timeouts(&self) -> Timeouts {
+		let default = Timeouts::default();
+		Timeouts {
+			connect: self.connect_timeout.map(Duration::from_secs).unwrap_or(default.connect),
+			read: self.read_timeout.map(Duration::from_secs).unwrap_or(default.read),
+		}
+	}
+
+	pub fn retries(&self) -> u32 {
+		self.retries.unwrap_or(3)
+	}
+
+	/// Get the password using the password command, the password file or
+	/// (deprecated) the MAILPASSWORD envvar, in that order.
+	pub fn password(&self) -> Result<String> {
+		if let Some(command) = self.password_command.as_ref() {
+			return run_secret_command("password-command", command);
+		}
+		if let Some(file) = self.password_file.as_ref() {
+			let unreadable = |e: io::Error| InboxidError::Config(format!("failed to read password-file {:?}: {}", file, e));
+			let mode = fs::metadata(file).map_err(unreadable)?.permissions().mode();
+			if mode & 0o077 != 0 {
+				return Err(InboxidError::Config(format!("password-file {:?} is accessible by other users (mode {:o}), use chmod 600", file, mode & 0o777)));
+			}
+			let content = fs::read_to_string(file).map_err(unreadable)?;
+			return first_line(&content).ok_or_else(|| InboxidError::Config(format!("password-file {:?} is empty", file)));
+		}
+		env::var("MAILPASSWORD").map_err(|_| InboxidError::Config("no password configured: tried password-command, password-file and envvar MAILPASSWORD".to_owned()))
+	}
+
+	/// Get an OAuth2 access token using the token command.
+	/// If `refresh` is set, the token refresh command is preferred.
+	pub fn token(&self, refresh: bool) -> Result<String> {
+		let command = if refresh {
+			self.token_refresh_command.as_ref().or(self.token_command.as_ref())
+		} else {
+			self.token_command.as_ref()
+		};
+		let command = command.ok_or_else(|| InboxidError::Config("no token-command configured".to_owned()))?;
+		run_secret_command("token-command", command)
+	}
+
+	pub fn connect(&self) -> Result<ImapSession> {
+		if self.host.is_empty() || self.user.is_empty() {
+			return Err(InboxidError::Config("no IMAP host/user configured (set MAILHOST and MAILUSER or configure an account)".to_owned()));
+		}
+		if self.auth == Auth::Login {
+			return connect(&self.host, self.port(), self.security, &self.tls, self.timeouts(), &self.user, &self.password()?);
+		}
+		let mechanism = match self.auth {
+			Auth::Oauthbearer => "OAUTHBEARER",
+			_ => "XOAUTH2"
+		};
+		let client = open_client(&self.host, self.port(), self.security, &self.tls, self.timeouts())?;
+		println!("authenticating using {}..", mechanism);
+		let auth = OAuth2 {
+			account: self,
+			token: self.token(false)?
+		};
+		match client.authenticate(mechanism, &auth) {
+			Ok(session) => Ok(session),
+			// only a rejected token is refreshed, other errors are reported as they are
+			Err((e @ imap::Error::No(_), client)) | Err((e @ imap::Error::Bad(_), client)) => {
+				println!("token rejected ({}), refreshing..", e);
+				let auth = OAuth2 {
+					account: self,
+					token: self.token(true)?
+				};
+				Ok(client.authenticate(mechanism, &auth).map_err(|e| e.0)?)
+			},
+			Err((e, _)) => Err(e.into())
+		}
+	}
+
+	fn maildir_root(&self) -> Result<&Path> {
+		if self.maildir.as_os_str().is_empty() {
+			return Err(InboxidError::Config("no maildir configured (set MAILDIR or configure an account)".to_owned()));
+		}
+		Ok(&self.maildir)
+	}
+
+	/// Get the local names of all mailboxes (see [local_mailbox_name]), including nested ones.
+	pub fn get_maildirs(&self) -> Result<Vec<String>> {
+		let mut dirs = vec![];
+		let mut todo = vec![(self.maildir_root()?.to_owned(), String::new())];
+		while let Some((path, prefix)) = todo.pop() {
+			for dir in fs::read_dir(path)? {
+				let dir = dir?;
+				if !dir.file_type()?.is_dir() {
+					continue;
+				}
+				let name = dir.file_name().into_string().map_err(|x| InboxidError::Parse(format!("failed to decode directory name {:?}", x)))?;
+				// skip hidden directories and the directories of the maildir itself
+				if name.starts_with('.') || (!prefix.is_empty() && ["cur", "new", "tmp"].contains(&&*name)) {
+					continue;
+				}
+				let name = format!("{}{}", prefix, name);
+				// parents of nested mailboxes are not necessarily mailboxes themselves
+				if dir.path().join("cur").is_dir() {
+					dirs.push(name.clone());
+				}
+				todo.push((dir.path(), format!("{}/", name)));
+			}
+		}
+		Ok(dirs)
+	}
+
+	pub fn get_maildir(&self, mailbox: &str) -> Result<Maildir> {
+		let maildir = Maildir::from(self.maildir_root()?.join(mailbox));
+		maildir.create_dirs()?;
+		Ok(maildir)
+	}
+
+	pub fn get_db(&self) -> Result<Connection> {
+		if self.db.as_os_str().is_empty() {
+			return Err(InboxidError::Config("no database configured (set MAILDB or configure an account)".to_owned()));
+		}
+		let mut conn = Connection::open(&self.db)?;
+		migrate(&mut conn)?;
+
+		Ok(conn)
+	}
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub struct Browse {
@@ -628,6 +1346,11 @@ pub struct Browse {
 	#[serde(deserialize_with = "deserialize_style")]
 	#[serde(serialize_with = "serialize_style")]
 	pub deleted_style: Style,
+	/// style of mails in `new`, combined with the other styles
+	#[serde(default = "default_recent_style")]
+	#[serde(deserialize_with = "deserialize_style")]
+	#[serde(serialize_with = "serialize_style")]
+	pub recent_style: Style,
 	#[serde(default)]
 	pub base_save_path: PathBuf,
 }
@@ -639,6 +1362,7 @@ impl Default for Browse {
 			unread_style: default_unread_style(),
 			trashed_style: default_trashed_style(),
 			deleted_style: default_deleted_style(),
+			recent_style: default_recent_style(),
 			base_save_path: directories_next::UserDirs::new().expect("no user dirs").download_dir().expect("no download directory").to_owned()
 		}
 	}
@@ -707,49 +1431,76 @@ fn default_deleted_style() -> Style {
 	Effect::Strikethrough.into()
 }
 
+fn default_recent_style() -> Style {
+	Effect::Bold.into()
+}
+
+/// Replace the [SYNCED_FLAGS] in the maildir flags `f` by the IMAP flags.
 pub fn imap_flags_to_maildir(mut f: String, flags: &[Flag]) -> String {
-	if flags.contains(&Flag::Seen) {
-		f.push('S');
-	} else {
-		f = f.replace('S', "");
-	}
-	if flags.contains(&Flag::Answered) {
-		f.push('R');
-	} else {
-		f = f.replace('R', "");
-	}
-	if flags.contains(&Flag::Flagged) {
-		f.push('F');
-	} else {
-		f = f.replace('F', "");
+	for &flag in SYNCED_FLAGS {
+		f = f.replace(flag, "");
+		if flags.iter().any(|x| imap_flag_to_maildir(x) == Some(flag)) {
+			f.push(flag);
+		}
 	}
 	f
 }
 
+/// Merge the [SYNCED_FLAGS] changed locally and on the server since the last sync.
+/// Without `base`, flags set on either side are kept. A mail marked as [UNREAD] was always changed locally.
+/// Returns the merged flags (only [SYNCED_FLAGS]).
+pub fn merge_flags(local: &str, remote: &[Flag], base: Option<&str>, winner: ConflictWinner) -> String {
+	let remote = imap_flags_to_maildir(String::new(), remote);
+	let mut merged = String::new();
+	for &flag in SYNCED_FLAGS {
+		let l = local.contains(flag);
+		let r = remote.contains(flag);
+		let b = base.map(|x| x.contains(flag)).unwrap_or(l && r);
+		let local_changed = l != b || (flag == SEEN && local.contains(UNREAD));
+		let remote_changed = r != b;
+		let set = if local_changed && remote_changed && l != r {
+			match winner {
+				ConflictWinner::Local => l,
+				ConflictWinner::Remote => r
+			}
+		} else if local_changed {
+			l
+		} else {
+			r
+		};
+		if set {
+			merged.push(flag);
+		}
+	}
+	merged
+}
+
 pub fn imap_flag_to_maildir(flag: &Flag) -> Option<char> {
 	match flag {
-		Flag::Seen => Some('S'),
-		Flag::Answered => Some('R'),
-		Flag::Flagged => Some('F'),
-		Flag::Deleted => Some('T'),
+		Flag::Seen => Some(SEEN),
+		Flag::Answered => Some(REPLIED),
+		Flag::Flagged => Some(FLAGGED),
+		Flag::Deleted => Some(TRASHED),
+		Flag::Draft => Some(DRAFT),
+		Flag::Custom(x) if x.eq_ignore_ascii_case(FORWARDED) => Some(PASSED),
 		_ => None
 	}
 }
 
-pub fn maildir_flags_to_imap(flags: &str) -> Vec<Flag> {
-	let mut x = vec![];
-	for c in flags.chars() {
-		if let Some(f) = match c {
-			REPLIED => Some(Flag::Answered),
-			SEEN => Some(Flag::Seen),
-			FLAGGED => Some(Flag::Flagged),
-			TRASHED => Some(Flag::Deleted),
-			_ => None
-		} {
-			x.push(f);
-		}
+pub fn maildir_flag_to_imap(flag: char) -> Option<Flag<'static>> {
+	match flag {
+		REPLIED => Some(Flag::Answered),
+		SEEN => Some(Flag::Seen),
+		FLAGGED => Some(Flag::Flagged),
+		TRASHED => Some(Flag::Deleted),
+		DRAFT => Some(Flag::Draft),
+		PASSED => Some(Flag::Custom(Cow::Borrowed(FORWARDED))),
+		_ => None
 	}
-	x
+}
+
+pub fn maildir_flags_to_imap(flags: &str) -> Vec<Flag<'static>> {
+	flags.chars().filter_map(maildir_flag_to_imap).collect()
 }
 
 pub fn imap_flags_to_cmd(flags: &[Flag]) -> String {
@@ -0,0 +1,133 @@
+use std::fs;
+
+use itertools::Itertools;
+use rusqlite::{Connection, params};
+
+use crate::{Account, ImapSession, InboxidError, Result};
+
+/// Directory names with a special meaning inside a maildir.
+const RESERVED: &[&str] = &["cur", "new", "tmp"];
+
+/// Decode a mailbox name in modified UTF-7 (RFC 3501, section 5.1.3).
+pub fn decode_mailbox_name(name: &str) -> Result<String> {
+	let invalid = || InboxidError::Parse(format!("invalid mailbox name {:?}", name));
+	let mut decoded = String::new();
+	let mut rest = name;
+	while let Some(start) = rest.find('&') {
+		decoded += &rest[..start];
+		rest = &rest[start + 1..];
+		let end = rest.find('-').ok_or_else(invalid)?;
+		let encoded = &rest[..end];
+		rest = &rest[end + 1..];
+		if encoded.is_empty() {
+			decoded.push('&');
+			continue;
+		}
+		// modified BASE64 of UTF-16BE, without padding
+		let mut bits = 0u32;
+		let mut len = 0;
+		let mut units = Vec::new();
+		for c in encoded.bytes() {
+			let value = match c {
+				b'A'..=b'Z' => c - b'A',
+				b'a'..=b'z' => c - b'a' + 26,
+				b'0'..=b'9' => c - b'0' + 52,
+				b'+' => 62,
+				b',' => 63,
+				_ => return Err(invalid())
+			};
+			bits = bits << 6 | value as u32;
+			len += 6;
+			if len >= 16 {
+				len -= 16;
+				units.push((bits >> len) as u16);
+				bits &= (1 << len) - 1;
+			}
+		}
+		decoded += &String::from_utf16(&units).map_err(|_| invalid())?;
+	}
+	decoded += rest;
+	Ok(decoded)
+}
+
+/// Escape one level of the hierarchy for use as directory name.
+/// `%` and `/` are percent-encoded, as is the first character of levels starting with `.`
+/// (hidden, like [crate::GONE]) and of `cur`, `new` and `tmp`.
+fn escape_level(level: &str) -> String {
+	let mut escaped = String::new();
+	for (i, c) in level.chars().enumerate() {
+		if c == '%' || c == '/' || (i == 0 && (c == '.' || RESERVED.contains(&level))) {
+			escaped += &format!("%{:02X}", c as u32);
+		} else {
+			escaped.push(c);
+		}
+	}
+	escaped
+}
+
+/// Reverse the escaping of a level of a local mailbox name, for display.
+pub fn unescape_mailbox_level(level: &str) -> String {
+	let mut unescaped = String::new();
+	let mut rest = level;
+	while let Some(start) = rest.find('%') {
+		unescaped += &rest[..start];
+		match rest.get(start + 1..start + 3).and_then(|x| u8::from_str_radix(x, 16).ok()) {
+			Some(c) => {
+				unescaped.push(c as char);
+				rest = &rest[start + 3..];
+			},
+			None => {
+				unescaped.push('%');
+				rest = &rest[start + 1..];
+			}
+		}
+	}
+	unescaped += rest;
+	unescaped
+}
+
+/// Get the local name of a mailbox on the server, given the hierarchy delimiter from LIST.
+/// The levels of the hierarchy are decoded, escaped and joined by `/`:
+/// every mailbox is stored in the directory of the same name below the maildir root
+/// and child mailboxes are nested in their parent directory.
+pub fn local_mailbox_name(name: &str, delimiter: Option<&str>) -> String {
+	let levels = match delimiter {
+		Some(delimiter) if !delimiter.is_empty() => name.split(delimiter).collect_vec(),
+		_ => vec![name]
+	};
+	levels.into_iter()
+		// keep undecodable names as they are
+		.map(|x| escape_level(&decode_mailbox_name(x).unwrap_or_else(|_| x.to_owned())))
+		.join("/")
+}
+
+/// Find the name on the server of a local mailbox (see [local_mailbox_name]).
+pub fn server_mailbox_name(session: &mut ImapSession, mailbox: &str) -> Result<String> {
+	let list = session.list(None, Some("*")).map_err(|e| InboxidError::imap(mailbox, e))?;
+	let name = list.iter().find(|x| local_mailbox_name(x.name(), x.delimiter()) == mailbox).map(|x| x.name().to_owned());
+	Ok(name.unwrap_or_else(|| mailbox.to_owned()))
+}
+
+/// Move a mailbox to another local name: its directory and its rows in the database.
+/// Fails without changing anything if both directories exist.
+pub fn rename_mailbox(conn: &Connection, account: &Account, old: &str, new: &str) -> Result<()> {
+	let root = account.maildir_root()?;
+	let (from, to) = (root.join(old), root.join(new));
+	if from.is_dir() {
+		if to.exists() {
+			return Err(InboxidError::Maildir {
+				mailbox: Some(old.to_owned()),
+				id: None,
+				source: format!("can not move to {}, it exists already", new).into()
+			});
+		}
+		if let Some(parent) = to.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		fs::rename(&from, &to)?;
+	}
+	for table in &["mail", "mailbox_state", "remote_mail", "search_doc"] {
+		conn.execute(&format!("UPDATE {} SET mailbox = ? WHERE mailbox = ?", table), params![new, old])?;
+	}
+	Ok(())
+}
@@ -0,0 +1,127 @@
+use itertools::Itertools;
+use mailparse::{DispositionType, MailHeaderMap, ParsedMail};
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::{MaildirID, Result};
+
+/// Line width used to render HTML bodies as text.
+const HTML_WIDTH: usize = 100;
+
+/// A mail matching a search query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+	pub mailbox: String,
+	pub id: MaildirID,
+	pub subject: String,
+	/// the matching part of the mail, matches are enclosed in [brackets]
+	pub snippet: String,
+}
+
+/// Add a mail to the full-text search index, replacing any previous entry.
+pub fn index_mail(conn: &Connection, mailbox: &str, id: MaildirID, mail: &ParsedMail) -> Result<()> {
+	let headers = mail.get_headers();
+	let subject = headers.get_all_values("Subject").join(" ");
+	let from = headers.get_all_values("From").join(", ");
+	let to = headers.get_all_values("To").into_iter().chain(headers.get_all_values("Cc")).join(", ");
+	let mut body = String::new();
+	body_text(mail, &mut body);
+
+	let doc = new_document(conn, mailbox, id)?;
+	conn.prepare_cached("INSERT INTO search_index (rowid, subject, sender, recipients, body) VALUES (?,?,?,?,?)")?
+		.execute(params![doc, subject, from, to, body])?;
+	Ok(())
+}
+
+/// Index a copy of an already indexed mail, without parsing it again.
+pub fn copy_index(conn: &Connection, mailbox: &str, id: MaildirID, new_mailbox: &str, new_id: MaildirID) -> Result<()> {
+	let source = match document(conn, mailbox, id)? {
+		Some(x) => x,
+		None => return Ok(())
+	};
+	let doc = new_document(conn, new_mailbox, new_id)?;
+	conn.prepare_cached("INSERT INTO search_index (rowid, subject, sender, recipients, body)
+		SELECT ?, subject, sender, recipients, body FROM search_index WHERE rowid = ?")?
+		.execute(params![doc, source])?;
+	Ok(())
+}
+
+/// Remove a mail from the search index.
+pub fn unindex_mail(conn: &Connection, mailbox: &str, id: MaildirID) -> Result<()> {
+	if let Some(doc) = document(conn, mailbox, id)? {
+		conn.prepare_cached("DELETE FROM search_index WHERE rowid = ?")?.execute(params![doc])?;
+		conn.prepare_cached("DELETE FROM search_doc WHERE id = ?")?.execute(params![doc])?;
+	}
+	Ok(())
+}
+
+/// Remove all mails of a mailbox from the search index.
+pub fn unindex_mailbox(conn: &Connection, mailbox: &str) -> Result<()> {
+	conn.execute("DELETE FROM search_index WHERE rowid IN (SELECT id FROM search_doc WHERE mailbox = ?)", params![mailbox])?;
+	conn.execute("DELETE FROM search_doc WHERE mailbox = ?", params![mailbox])?;
+	Ok(())
+}
+
+/// Search all indexed mails, best matches first.
+/// See the SQLite FTS5 documentation for the query syntax, e.g.
+/// `sender:alice AND (meeting OR call)`.
+pub fn search(conn: &Connection, query: &str, limit: u32) -> Result<Vec<SearchHit>> {
+	let mut stmt = conn.prepare("SELECT doc.mailbox, doc.uid_validity, doc.uid, search_index.subject,
+			snippet(search_index, -1, '[', ']', '...', 16)
+		FROM search_index JOIN search_doc doc ON doc.id = search_index.rowid
+		WHERE search_index MATCH ? ORDER BY rank LIMIT ?")?;
+	let hits = stmt.query_map(params![query, limit], |row| Ok(SearchHit {
+		mailbox: row.get(0)?,
+		id: MaildirID::from_row(row, 1)?,
+		subject: row.get(3)?,
+		snippet: row.get(4)?,
+	}))?.collect::<rusqlite::Result<_>>()?;
+	Ok(hits)
+}
+
+fn document(conn: &Connection, mailbox: &str, id: MaildirID) -> Result<Option<i64>> {
+	let doc = conn.prepare_cached("SELECT id FROM search_doc WHERE mailbox = ? AND uid_validity = ? AND uid = ?")?
+		.query_row(params![mailbox, id.uid_validity, id.uid], |row| row.get(0))
+		.optional()?;
+	Ok(doc)
+}
+
+/// Get the (now empty) index entry of a mail.
+fn new_document(conn: &Connection, mailbox: &str, id: MaildirID) -> Result<i64> {
+	if let Some(doc) = document(conn, mailbox, id)? {
+		conn.prepare_cached("DELETE FROM search_index WHERE rowid = ?")?.execute(params![doc])?;
+		return Ok(doc);
+	}
+	conn.prepare_cached("INSERT INTO search_doc (mailbox, uid_validity, uid) VALUES (?,?,?)")?
+		.execute(params![mailbox, id.uid_validity, id.uid])?;
+	Ok(conn.last_insert_rowid())
+}
+
+/// Collect the readable text of a mail: plain text parts and rendered HTML parts.
+/// Attachments and undecodable parts are skipped.
+fn body_text(mail: &ParsedMail, out: &mut String) {
+	if mail.get_content_disposition().disposition == DispositionType::Attachment {
+		return;
+	}
+	let mime = &mail.ctype.mimetype;
+	if mime == "multipart/alternative" {
+		// all parts have the same content, prefer plain text
+		let part = mail.subparts.iter().find(|x| x.ctype.mimetype == "text/plain").or_else(|| mail.subparts.last());
+		if let Some(part) = part {
+			body_text(part, out);
+		}
+	} else if mime.starts_with("multipart/") {
+		for part in &mail.subparts {
+			body_text(part, out);
+		}
+	} else if mime == "text/plain" {
+		if let Ok(text) = mail.get_body() {
+			out.push_str(&text);
+			out.push('\n');
+		}
+	} else if mime == "text/html" {
+		if let Ok(html) = mail.get_body() {
+			out.push_str(&html2text::from_read(html.as_bytes(), HTML_WIDTH));
+			out.push('\n');
+		}
+	}
+}
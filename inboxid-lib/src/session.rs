@@ -0,0 +1,153 @@
+use std::{cmp, ops::{Deref, DerefMut}, thread, time::Duration};
+
+use imap::types::Mailbox;
+
+use crate::{Account, ImapSession, InboxidError, Result};
+
+/// Delay before the first reconnection attempt, doubled after every failure.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// An [ImapSession] that transparently reconnects if the connection is lost.
+///
+/// Idempotent operations should be run using [ReconnectingSession::retry]:
+/// if the connection drops, the session logs in again, re-selects the
+/// current mailbox and repeats the operation (at most `retries` times,
+/// with exponential backoff). All other operations can be run directly
+/// on the session and fail as usual.
+pub struct ReconnectingSession<'a> {
+	account: &'a Account,
+	session: ImapSession,
+	/// currently selected mailbox, its UIDVALIDITY and whether it is read-only
+	selected: Option<(String, Option<u32>, bool)>,
+	/// whether QRESYNC is enabled (again after reconnecting)
+	qresync: bool,
+}
+
+impl<'a> ReconnectingSession<'a> {
+	pub fn connect(account: &'a Account) -> Result<Self> {
+		Ok(Self {
+			account,
+			session: account.connect()?,
+			selected: None,
+			qresync: false,
+		})
+	}
+
+	/// Connect and enable QRESYNC (RFC 7162), if the server supports it.
+	pub fn connect_qresync(account: &'a Account) -> Result<Self> {
+		let mut session = account.connect()?;
+		let qresync = enable_qresync(&mut session)?;
+		Ok(Self {
+			account,
+			session,
+			selected: None,
+			qresync,
+		})
+	}
+
+	/// Whether QRESYNC is enabled, see [ReconnectingSession::connect_qresync].
+	pub fn qresync(&self) -> bool {
+		self.qresync
+	}
+
+	pub fn select(&mut self, mailbox: &str) -> Result<Mailbox> {
+		self.open(mailbox, false)
+	}
+
+	pub fn examine(&mut self, mailbox: &str) -> Result<Mailbox> {
+		self.open(mailbox, true)
+	}
+
+	fn open(&mut self, mailbox: &str, read_only: bool) -> Result<Mailbox> {
+		self.selected = None;
+		let resp = self.retry(|session| if read_only {
+			session.examine(mailbox)
+		} else {
+			session.select(mailbox)
+		}).map_err(|e| e.in_mailbox(mailbox))?;
+		self.set_selected(mailbox, resp.uid_validity, read_only);
+		Ok(resp)
+	}
+
+	/// Remember the selected mailbox, if it was selected using a raw command.
+	pub fn set_selected(&mut self, mailbox: &str, uid_validity: Option<u32>, read_only: bool) {
+		self.selected = Some((mailbox.to_owned(), uid_validity, read_only));
+	}
+
+	/// Run an idempotent operation, reconnecting and retrying if the connection is lost.
+	pub fn retry<T>(&mut self, mut f: impl FnMut(&mut ImapSession) -> imap::error::Result<T>) -> Result<T> {
+		let mut backoff = MIN_BACKOFF;
+		let mut attempts = 0;
+		loop {
+			let mut error = match f(&mut self.session) {
+				Ok(x) => return Ok(x),
+				Err(e) => InboxidError::Imap {
+					mailbox: self.selected.as_ref().map(|x| x.0.clone()),
+					source: e,
+				}
+			};
+			loop {
+				if !error.is_connection_lost() || attempts >= self.account.retries() {
+					return Err(error);
+				}
+				attempts += 1;
+				println!("{}, reconnecting in {}s..", error, backoff.as_secs());
+				thread::sleep(backoff);
+				backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+				match self.reconnect() {
+					Ok(()) => break,
+					Err(e) => error = e
+				}
+			}
+		}
+	}
+
+	fn reconnect(&mut self) -> Result<()> {
+		self.session = self.account.connect()?;
+		if self.qresync {
+			self.qresync = enable_qresync(&mut self.session)?;
+		}
+		if let Some((mailbox, uid_validity, read_only)) = self.selected.clone() {
+			println!("selecting {} again", mailbox);
+			let resp = if read_only {
+				self.session.examine(&mailbox)
+			} else {
+				self.session.select(&mailbox)
+			}.map_err(|e| InboxidError::imap(&mailbox, e))?;
+			if resp.uid_validity != uid_validity {
+				self.selected = None;
+				return Err(InboxidError::UidValidityChanged {
+					mailbox,
+					expected: uid_validity.unwrap_or_default(),
+					actual: resp.uid_validity,
+				});
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Enable QRESYNC if the server supports it.
+/// ENABLE is only allowed before a mailbox is selected (RFC 5161).
+fn enable_qresync(session: &mut ImapSession) -> Result<bool> {
+	if !session.capabilities()?.has_str("QRESYNC") {
+		return Ok(false);
+	}
+	session.run_command_and_read_response("ENABLE QRESYNC")?;
+	Ok(true)
+}
+
+impl Deref for ReconnectingSession<'_> {
+	type Target = ImapSession;
+
+	fn deref(&self) -> &Self::Target {
+		&self.session
+	}
+}
+
+impl DerefMut for ReconnectingSession<'_> {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		&mut self.session
+	}
+}
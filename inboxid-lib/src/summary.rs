@@ -0,0 +1,219 @@
+use std::{collections::HashMap, convert::TryFrom, fs, path::Path, time::UNIX_EPOCH};
+
+use maildir::Maildir;
+use mailparse::{DispositionType, MailHeaderMap, ParsedMail, dateparse, parse_mail};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+
+use crate::{HeadersExtension, InboxidError, MaildirID, Result, add_to_thread};
+
+/// The header fields needed to list a mail, cached in the mail table
+/// so listings don't have to parse every mail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailSummary {
+	pub id: MaildirID,
+	/// maildir flags
+	pub flags: String,
+	pub message_id: String,
+	pub subject: String,
+	/// raw From header
+	pub from: String,
+	/// date of the mail as unix timestamp
+	pub date: i64,
+	pub date_source: DateSource,
+	pub in_reply_to: String,
+	pub references: String,
+	/// size of the message in bytes
+	pub size: u64,
+	pub has_attachment: bool,
+	/// whether the mail is in `new`
+	pub new: bool,
+}
+
+/// Where the date of a mail was taken from, see [mail_date].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DateSource {
+	Header,
+	Received,
+	Mtime,
+	Epoch,
+}
+
+impl DateSource {
+	/// Whether the date was taken from the Date header.
+	pub fn is_certain(self) -> bool {
+		self == DateSource::Header
+	}
+
+	fn to_i64(self) -> i64 {
+		match self {
+			DateSource::Header => 0,
+			DateSource::Received => 1,
+			DateSource::Mtime => 2,
+			DateSource::Epoch => 3,
+		}
+	}
+
+	fn from_i64(x: i64) -> Self {
+		match x {
+			0 => DateSource::Header,
+			1 => DateSource::Received,
+			2 => DateSource::Mtime,
+			_ => DateSource::Epoch,
+		}
+	}
+}
+
+/// Get the date of a mail from the Date header. If it is missing or invalid,
+/// use the newest Received header, the modification time of the file or the Unix epoch.
+pub fn mail_date<T: MailHeaderMap + ?Sized>(headers: &T, path: Option<&Path>) -> (i64, DateSource) {
+	if let Some(date) = headers.get_first_value("Date").and_then(|x| dateparse(&x).ok()) {
+		return (date, DateSource::Header);
+	}
+	// the date follows the last semicolon
+	if let Some(date) = headers.get_first_value("Received").and_then(|x| dateparse(x.rsplit(';').next()?.trim()).ok()) {
+		return (date, DateSource::Received);
+	}
+	let mtime = path.and_then(|x| fs::metadata(x).ok())
+		.and_then(|x| x.modified().ok())
+		.and_then(|x| x.duration_since(UNIX_EPOCH).ok());
+	if let Some(mtime) = mtime {
+		return (mtime.as_secs() as i64, DateSource::Mtime);
+	}
+	(0, DateSource::Epoch)
+}
+
+impl MailSummary {
+	/// `path` is the location of the mail file, used if the mail has no valid date.
+	pub fn new(mailbox: &str, id: MaildirID, flags: &str, mail: &ParsedMail, size: u64, path: Option<&Path>) -> Self {
+		let headers = mail.get_headers();
+		let (date, date_source) = mail_date(&headers, path);
+		Self {
+			id,
+			flags: flags.to_owned(),
+			message_id: headers.message_id(mailbox, id),
+			subject: headers.get_header("Subject"),
+			from: headers.get_header("From"),
+			date,
+			date_source,
+			in_reply_to: headers.get_header("In-Reply-To"),
+			references: headers.get_header("References"),
+			size,
+			has_attachment: has_attachment(mail),
+			new: false,
+		}
+	}
+}
+
+/// Cache the summary of a mail already stored in the mail table
+/// and add the mail to its thread.
+pub fn save_summary(conn: &Connection, mailbox: &str, summary: &MailSummary) -> Result<()> {
+	conn.prepare_cached("UPDATE mail SET subject = ?, sender = ?, date = ?, date_source = ?, in_reply_to = ?, refs = ?, size = ?, has_attachment = ?
+		WHERE mailbox = ? AND uid_validity = ? AND uid = ?")?
+		.execute(params![
+			summary.subject, summary.from, summary.date, summary.date_source.to_i64(), summary.in_reply_to, summary.references, summary.size as i64, summary.has_attachment,
+			mailbox, summary.id.uid_validity, summary.id.uid
+		])?;
+	add_to_thread(conn, &summary.message_id, &summary.in_reply_to, &summary.references)?;
+	Ok(())
+}
+
+/// Copy the cached summary of a mail to its hardlinked copy.
+pub fn copy_summary(conn: &Connection, mailbox: &str, id: MaildirID, new_mailbox: &str, new_id: MaildirID) -> Result<()> {
+	conn.prepare_cached("UPDATE mail SET (subject, sender, date, date_source, in_reply_to, refs, size, has_attachment) =
+		(SELECT subject, sender, date, date_source, in_reply_to, refs, size, has_attachment FROM mail WHERE mailbox = ? AND uid_validity = ? AND uid = ?)
+		WHERE mailbox = ? AND uid_validity = ? AND uid = ?")?
+		.execute(params![mailbox, id.uid_validity, id.uid, new_mailbox, new_id.uid_validity, new_id.uid])?;
+	Ok(())
+}
+
+const SUMMARY_COLUMNS: &str = "uid_validity, uid, flags, message_id, subject, sender, date, in_reply_to, refs, size, has_attachment, date_source";
+
+fn summary_from_row(row: &Row) -> rusqlite::Result<MailSummary> {
+	Ok(MailSummary {
+		id: MaildirID::from_row(row, 0)?,
+		flags: row.get(2)?,
+		message_id: row.get(3)?,
+		subject: row.get(4)?,
+		from: row.get(5)?,
+		date: row.get(6)?,
+		in_reply_to: row.get(7)?,
+		references: row.get(8)?,
+		size: row.get::<_, i64>(9)? as u64,
+		has_attachment: row.get(10)?,
+		date_source: DateSource::from_i64(row.get(11)?),
+		new: false,
+	})
+}
+
+/// Get the summary of a single mail, with the flags stored in the mail table.
+/// If it is not cached yet, the mail is parsed and cached (using the flags of the file).
+/// None if the mail is not in the maildir.
+pub fn load_summary(conn: &Connection, mailbox: &str, maildir: &Maildir, id: MaildirID) -> Result<Option<MailSummary>> {
+	let summary = conn.prepare_cached(&format!("SELECT {} FROM mail
+		WHERE mailbox = ? AND uid_validity = ? AND uid = ? AND subject IS NOT NULL", SUMMARY_COLUMNS))?
+		.query_row(params![mailbox, id.uid_validity, id.uid], summary_from_row)
+		.optional()?;
+	if summary.is_some() {
+		return Ok(summary);
+	}
+	let entry = match maildir.find(&id.to_string()) {
+		Some(x) => x,
+		None => return Ok(None)
+	};
+	let data = fs::read(entry.path())?;
+	let mail = parse_mail(&data).map_err(|e| InboxidError::maildir(mailbox, id, e))?;
+	let summary = MailSummary::new(mailbox, id, entry.flags(), &mail, data.len() as u64, Some(entry.path().as_path()));
+	save_summary(conn, mailbox, &summary)?;
+	Ok(Some(summary))
+}
+
+/// Get the summaries of all mails in `cur` and `new` of the mailbox, sorted by ID.
+/// Only mails missing from the cache are parsed (in parallel) and cached, if they are in the mail table.
+pub fn load_summaries(conn: &Connection, mailbox: &str, maildir: &Maildir) -> Result<Vec<MailSummary>> {
+	let mut stmt = conn.prepare(&format!("SELECT {} FROM mail WHERE mailbox = ? AND subject IS NOT NULL", SUMMARY_COLUMNS))?;
+	let mut cached = stmt.query_map(params![mailbox], summary_from_row)?
+		.map(|x| x.map(|x| (x.id, x)))
+		.collect::<rusqlite::Result<HashMap<_, _>>>()?;
+
+	let mut entries = Vec::new();
+	for (list, new) in vec![(maildir.list_cur(), false), (maildir.list_new(), true)] {
+		for entry in list {
+			let entry = entry?;
+			entries.push((MaildirID::try_from(entry.id())?, new, entry.flags().to_owned(), entry.path().to_owned()));
+		}
+	}
+	entries.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+	// duplicates are reported by inboxid-fsck, only list the mail in cur
+	entries.dedup_by_key(|x| x.0);
+	// parse the mails missing from the cache on all cores
+	let mut parsed = entries.par_iter()
+		.filter(|x| !cached.contains_key(&x.0))
+		.map(|(id, _, flags, path)| -> Result<(MaildirID, MailSummary)> {
+			let data = fs::read(path)?;
+			let mail = parse_mail(&data).map_err(|e| InboxidError::maildir(mailbox, *id, e))?;
+			Ok((*id, MailSummary::new(mailbox, *id, flags, &mail, data.len() as u64, Some(path.as_path()))))
+		})
+		.collect::<Result<HashMap<_, _>>>()?;
+
+	let mut summaries = Vec::new();
+	for (id, new, flags, _) in entries {
+		if let Some(mut summary) = cached.remove(&id) {
+			summary.flags = flags;
+			summary.new = new;
+			summaries.push(summary);
+			continue;
+		}
+		if let Some(mut summary) = parsed.remove(&id) {
+			save_summary(conn, mailbox, &summary)?;
+			summary.new = new;
+			summaries.push(summary);
+		}
+	}
+	Ok(summaries)
+}
+
+fn has_attachment(mail: &ParsedMail) -> bool {
+	mail.get_content_disposition().disposition == DispositionType::Attachment
+		|| mail.subparts.iter().any(has_attachment)
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeSet;
+
+use imap::types::Flag;
+use rusqlite::{Connection, params};
+
+use crate::{FORWARDED, InboxidError, MaildirID, Result};
+
+/// Whether the tag can be stored as IMAP keyword (an atom not starting with a backslash).
+/// [FORWARDED] is not a tag, it is stored as maildir flag.
+pub fn is_valid_tag(tag: &str) -> bool {
+	!tag.is_empty() && tag.chars().all(|c| c.is_ascii_graphic() && !"(){%*\"\\]".contains(c))
+		&& !tag.eq_ignore_ascii_case(FORWARDED)
+}
+
+/// Get the tags of a message, sorted.
+pub fn get_tags(conn: &Connection, message_id: &str) -> Result<Vec<String>> {
+	tags_from(conn, "tags", message_id)
+}
+
+/// Add a tag to a message. Fails if the tag can't be used as IMAP keyword.
+pub fn add_tag(conn: &Connection, message_id: &str, tag: &str) -> Result<()> {
+	if !is_valid_tag(tag) {
+		return Err(InboxidError::Parse(format!("invalid tag {:?}", tag)));
+	}
+	conn.prepare_cached("INSERT OR IGNORE INTO tags (message_id, tag) VALUES (?,?)")?
+		.execute(params![message_id, tag])?;
+	Ok(())
+}
+
+pub fn remove_tag(conn: &Connection, message_id: &str, tag: &str) -> Result<()> {
+	conn.prepare_cached("DELETE FROM tags WHERE message_id = ? AND tag = ?")?
+		.execute(params![message_id, tag])?;
+	Ok(())
+}
+
+/// Replace all tags of a message.
+pub fn set_tags(conn: &Connection, message_id: &str, tags: &[String]) -> Result<()> {
+	replace_tags(conn, "tags", message_id, tags)
+}
+
+/// Get the tags of a message as of the last sync.
+pub fn get_synced_tags(conn: &Connection, message_id: &str) -> Result<Vec<String>> {
+	tags_from(conn, "synced_tags", message_id)
+}
+
+pub fn set_synced_tags(conn: &Connection, message_id: &str, tags: &[String]) -> Result<()> {
+	replace_tags(conn, "synced_tags", message_id, tags)
+}
+
+/// Get all stored mails with the tag, as (mailbox, id).
+pub fn tagged_mails(conn: &Connection, tag: &str) -> Result<Vec<(String, MaildirID)>> {
+	let mut stmt = conn.prepare("SELECT mailbox, uid_validity, uid FROM mail
+		WHERE message_id IN (SELECT message_id FROM tags WHERE tag = ?)
+		ORDER BY mailbox, uid_validity, uid")?;
+	let mails = stmt.query_map(params![tag], |row| Ok((row.get(0)?, MaildirID::from_row(row, 1)?)))?
+		.collect::<rusqlite::Result<_>>()?;
+	Ok(mails)
+}
+
+/// Get all tags in use, sorted.
+pub fn all_tags(conn: &Connection) -> Result<Vec<String>> {
+	let mut stmt = conn.prepare("SELECT DISTINCT tag FROM tags ORDER BY tag")?;
+	let tags = stmt.query_map(params![], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+	Ok(tags)
+}
+
+fn tags_from(conn: &Connection, table: &str, message_id: &str) -> Result<Vec<String>> {
+	let mut stmt = conn.prepare_cached(&format!("SELECT tag FROM {} WHERE message_id = ? ORDER BY tag", table))?;
+	let tags = stmt.query_map(params![message_id], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+	Ok(tags)
+}
+
+fn replace_tags(conn: &Connection, table: &str, message_id: &str, tags: &[String]) -> Result<()> {
+	conn.prepare_cached(&format!("DELETE FROM {} WHERE message_id = ?", table))?
+		.execute(params![message_id])?;
+	let mut insert = conn.prepare_cached(&format!("INSERT OR IGNORE INTO {} (message_id, tag) VALUES (?,?)", table))?;
+	for tag in tags {
+		insert.execute(params![message_id, tag])?;
+	}
+	Ok(())
+}
+
+/// The IMAP keywords among the flags of a mail.
+pub fn keywords(flags: &[Flag]) -> Vec<String> {
+	flags.iter().filter_map(|x| match x {
+		Flag::Custom(x) if is_valid_tag(x) => Some(x.to_string()),
+		_ => None
+	}).collect()
+}
+
+/// Merge local and remote changes since the last sync:
+/// a tag is kept if both sides have it, or if one side added it.
+pub fn merge_tags(local: &[String], remote: &[String], base: &[String]) -> Vec<String> {
+	let local = local.iter().collect::<BTreeSet<_>>();
+	let remote = remote.iter().collect::<BTreeSet<_>>();
+	let base = base.iter().collect::<BTreeSet<_>>();
+	local.union(&remote)
+		.filter(|x| (local.contains(*x) && remote.contains(*x)) || !base.contains(*x))
+		.map(|x| x.to_string())
+		.collect()
+}
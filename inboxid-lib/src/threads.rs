@@ -0,0 +1,82 @@
+use itertools::Itertools;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::{MaildirID, Result};
+
+/// Thread ID of a conversation, shared by all mailboxes.
+pub type ThreadID = i64;
+
+/// Add a mail to the thread of the messages it references.
+/// Referenced messages that are not (yet) stored are recorded as well, so a
+/// reply and its parent end up in the same thread regardless of arrival order.
+/// If the mail connects several threads, they are merged.
+pub fn add_to_thread(conn: &Connection, message_id: &str, in_reply_to: &str, references: &str) -> Result<ThreadID> {
+	let ids = Some(message_id).into_iter()
+		.chain(references.split_whitespace())
+		.chain(in_reply_to.split_whitespace())
+		.unique()
+		.collect_vec();
+	let mut threads = Vec::new();
+	for id in &ids {
+		let thread: Option<ThreadID> = conn.prepare_cached("SELECT thread_id FROM threads WHERE message_id = ?")?
+			.query_row(params![id], |row| row.get(0))
+			.optional()?;
+		threads.extend(thread);
+	}
+	threads.sort_unstable();
+	threads.dedup();
+	let thread = match threads.first() {
+		Some(&x) => x,
+		None => conn.query_row("SELECT COALESCE(MAX(thread_id), 0) + 1 FROM threads", params![], |row| row.get(0))?
+	};
+	for &other in threads.iter().skip(1) {
+		conn.prepare_cached("UPDATE threads SET thread_id = ? WHERE thread_id = ?")?.execute(params![thread, other])?;
+	}
+	for id in &ids {
+		conn.prepare_cached("INSERT OR REPLACE INTO threads (message_id, thread_id) VALUES (?,?)")?.execute(params![id, thread])?;
+	}
+	Ok(thread)
+}
+
+/// Get the thread of a stored mail.
+pub fn thread_of(conn: &Connection, mailbox: &str, id: MaildirID) -> Result<Option<ThreadID>> {
+	let thread = conn.prepare_cached("SELECT threads.thread_id FROM mail JOIN threads ON threads.message_id = mail.message_id
+		WHERE mail.mailbox = ? AND mail.uid_validity = ? AND mail.uid = ?")?
+		.query_row(params![mailbox, id.uid_validity, id.uid], |row| row.get(0))
+		.optional()?;
+	Ok(thread)
+}
+
+/// Get all stored mails of a thread (in any mailbox), oldest first.
+pub fn messages_in_thread(conn: &Connection, thread: ThreadID) -> Result<Vec<(String, MaildirID)>> {
+	let mut stmt = conn.prepare_cached("SELECT mail.mailbox, mail.uid_validity, mail.uid FROM threads JOIN mail ON mail.message_id = threads.message_id
+		WHERE threads.thread_id = ? ORDER BY mail.date, mail.mailbox, mail.uid")?;
+	let mails = stmt.query_map(params![thread], |row| Ok((row.get(0)?, MaildirID::from_row(row, 1)?)))?
+		.collect::<rusqlite::Result<_>>()?;
+	Ok(mails)
+}
+
+/// Get all stored mails (in any mailbox) of the threads with a mail in the mailbox, oldest first.
+/// Returns the thread, mailbox, ID and message ID of every mail.
+pub fn threads_of_mailbox(conn: &Connection, mailbox: &str) -> Result<Vec<(ThreadID, String, MaildirID, String)>> {
+	let mut stmt = conn.prepare_cached("SELECT threads.thread_id, mail.mailbox, mail.uid_validity, mail.uid, mail.message_id
+		FROM threads JOIN mail ON mail.message_id = threads.message_id
+		WHERE threads.thread_id IN (SELECT threads.thread_id FROM mail JOIN threads ON threads.message_id = mail.message_id WHERE mail.mailbox = ?)
+		ORDER BY mail.date, mail.mailbox, mail.uid")?;
+	let mails = stmt.query_map(params![mailbox], |row| Ok((row.get(0)?, row.get(1)?, MaildirID::from_row(row, 2)?, row.get(4)?)))?
+		.collect::<rusqlite::Result<_>>()?;
+	Ok(mails)
+}
+
+/// Rebuild the thread index from the message IDs and references cached in the mail table.
+/// Only uses columns present since schema version 6, it is part of that migration.
+pub fn rebuild_threads(conn: &Connection) -> Result<()> {
+	conn.execute("DELETE FROM threads", params![])?;
+	let mut stmt = conn.prepare("SELECT message_id, COALESCE(in_reply_to, ''), COALESCE(refs, '') FROM mail ORDER BY date")?;
+	let mails = stmt.query_map(params![], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+		.collect::<rusqlite::Result<Vec<_>>>()?;
+	for (message_id, in_reply_to, references) in mails {
+		add_to_thread(conn, &message_id, &in_reply_to, &references)?;
+	}
+	Ok(())
+}
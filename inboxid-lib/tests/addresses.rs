@@ -0,0 +1,46 @@
+use mailparse::parse_mail;
+use rusqlite::Connection;
+
+use inboxid_lib::*;
+use inboxid_test_support::test_account;
+
+fn harvest(db: &Connection, message_id: &str, date: i64, mail: &str) {
+	let mail = parse_mail(mail.as_bytes()).unwrap();
+	let mut summary = MailSummary::new("INBOX", MaildirID::new(1, 1), "", &mail, 0, None);
+	summary.message_id = message_id.to_owned();
+	summary.date = date;
+	harvest_addresses(db, &summary, &mail).unwrap();
+}
+
+#[test]
+fn group_members_are_parsed() {
+	let addresses = parse_addresses("Alice <alice@example.com>, friends: bob@example.com, Carol <carol@example.com>;");
+	assert_eq!(addresses.iter().map(|x| &*x.addr).collect::<Vec<_>>(), vec!["alice@example.com", "bob@example.com", "carol@example.com"]);
+	assert!(parse_addresses("").is_empty());
+}
+
+#[test]
+fn addresses_are_harvested() {
+	let db = test_account("harvest").get_db().unwrap();
+	harvest(&db, "<1@example.com>", 100, "From: Alice <Alice@Example.com>\r\nTo: bob@example.com\r\nCc: Carol <carol@example.com>\r\n\r\nhi\r\n");
+	// the same message in another mailbox is not counted again
+	harvest(&db, "<1@example.com>", 100, "From: Alice <alice@example.com>\r\nTo: bob@example.com\r\n\r\nhi\r\n");
+	harvest(&db, "<2@example.com>", 200, "From: Alice Liddell <alice@example.com>\r\nTo: dave@example.com\r\n\r\nhi\r\n");
+
+	let addresses = all_addresses(&db).unwrap();
+	assert_eq!(addresses[0], Address {
+		address: "alice@example.com".to_owned(),
+		name: "Alice Liddell".to_owned(),
+		last_seen: 200,
+		count: 2,
+	});
+	assert_eq!(addresses.iter().map(|x| &*x.address).collect::<Vec<_>>(), vec![
+		"alice@example.com", "bob@example.com", "carol@example.com", "dave@example.com",
+	]);
+
+	let completions = |prefix| complete_address(&db, prefix, 10).unwrap().into_iter().map(|x| x.address).collect::<Vec<_>>();
+	assert_eq!(completions("ca"), vec!["carol@example.com"]);
+	assert_eq!(completions("Lid"), vec!["alice@example.com"]);
+	assert_eq!(completions("%"), Vec::<String>::new());
+	assert_eq!(completions("").first().map(|x| &**x), Some("alice@example.com"));
+}
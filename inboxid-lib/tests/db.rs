@@ -0,0 +1,73 @@
+use rusqlite::{Connection, params};
+
+use inboxid_lib::*;
+use inboxid_test_support::test_account;
+
+#[test]
+fn new_database_is_current() {
+	let account = test_account("new");
+	let db = account.get_db().unwrap();
+	assert_eq!(schema_version(&db).unwrap(), SCHEMA_VERSION);
+	db.execute("INSERT INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)", params!["INBOX", 100, 1, "<a@example.com>", "S"]).unwrap();
+	// the same mail can not be stored twice
+	assert!(db.execute("INSERT INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)", params!["INBOX", 100, 1, "<a@example.com>", "S"]).is_err());
+}
+
+#[test]
+fn unversioned_database_is_migrated() {
+	let account = test_account("unversioned");
+	{
+		let db = Connection::open(&account.db).unwrap();
+		db.execute("CREATE TABLE mail(mailbox STRING NOT NULL, uid INTEGER NOT NULL, message_id STRING NOT NULL, flags STRING NOT NULL)", params![]).unwrap();
+		// UIDVALIDITY and UID used to be packed into one column
+		let packed = ((3_000_000_000u64 << 32) | 7) as i64;
+		db.execute("INSERT INTO mail VALUES (?,?,?,?)", params!["INBOX", packed, "<a@example.com>", "S"]).unwrap();
+		db.execute("INSERT INTO mail VALUES (?,?,?,?)", params!["INBOX", packed, "<a@example.com>", "S"]).unwrap();
+	}
+	let db = account.get_db().unwrap();
+	assert_eq!(schema_version(&db).unwrap(), SCHEMA_VERSION);
+	let mut stmt = db.prepare("SELECT mailbox, uid_validity, uid, message_id FROM mail").unwrap();
+	let rows = stmt.query_map(params![], |row| Ok((row.get::<_, String>(0)?, MaildirID::from_row(row, 1)?, row.get::<_, String>(3)?))).unwrap()
+		.collect::<rusqlite::Result<Vec<_>>>().unwrap();
+	assert_eq!(rows, vec![("INBOX".to_owned(), MaildirID::new(3_000_000_000, 7), "<a@example.com>".to_owned())]);
+	drop(stmt);
+	// opening again is a no-op
+	drop(db);
+	account.get_db().unwrap();
+}
+
+#[test]
+fn cached_mails_are_threaded() {
+	let account = test_account("threads");
+	{
+		// schema version 5: summaries are cached, there is no thread index yet
+		let db = Connection::open(&account.db).unwrap();
+		db.execute_batch("CREATE TABLE schema_version(version INTEGER NOT NULL);
+			INSERT INTO schema_version VALUES (5);
+			CREATE TABLE mail(mailbox STRING NOT NULL, uid_validity INTEGER NOT NULL, uid INTEGER NOT NULL, message_id STRING NOT NULL, flags STRING NOT NULL,
+				subject STRING, sender STRING, date INTEGER, in_reply_to STRING, refs STRING, size INTEGER, has_attachment INTEGER);").unwrap();
+		let insert = "INSERT INTO mail VALUES (?,?,?,?,?,?,?,?,?,?,?,?)";
+		db.execute(insert, params!["INBOX", 100, 1, "<a@example.com>", "S", "hi", "", 1, "", "", 10, false]).unwrap();
+		db.execute(insert, params!["INBOX", 100, 2, "<b@example.com>", "S", "Re: hi", "", 2, "<a@example.com>", "<a@example.com>", 10, false]).unwrap();
+	}
+	let db = account.get_db().unwrap();
+	let thread = thread_of(&db, "INBOX", MaildirID::new(100, 1)).unwrap();
+	assert!(thread.is_some());
+	assert_eq!(thread_of(&db, "INBOX", MaildirID::new(100, 2)).unwrap(), thread);
+}
+
+#[test]
+fn newer_schema_is_refused() {
+	let account = test_account("newer");
+	let db = account.get_db().unwrap();
+	db.execute("UPDATE schema_version SET version = ?", params![SCHEMA_VERSION + 1]).unwrap();
+	drop(db);
+	match account.get_db() {
+		Err(InboxidError::UnsupportedSchema { version, supported }) => {
+			assert_eq!(version, SCHEMA_VERSION + 1);
+			assert_eq!(supported, SCHEMA_VERSION);
+		},
+		Err(e) => panic!("unexpected error: {}", e),
+		Ok(_) => panic!("newer schema was accepted"),
+	}
+}
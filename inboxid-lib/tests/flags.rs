@@ -0,0 +1,21 @@
+use imap::types::Flag;
+
+use inboxid_lib::*;
+
+#[test]
+fn flags_are_merged() {
+	use ConflictWinner::*;
+	// without the state of the last sync, flags of both sides are kept
+	assert_eq!(merge_flags("F", &[Flag::Seen], None, Local), "SF");
+	assert_eq!(merge_flags("U", &[Flag::Seen], None, Local), "");
+	// the side that changed a flag wins
+	assert_eq!(merge_flags("S", &[Flag::Seen, Flag::Flagged], Some("SF"), Local), "S");
+	assert_eq!(merge_flags("SF", &[Flag::Flagged], Some("SF"), Local), "F");
+	assert_eq!(merge_flags("SR", &[Flag::Seen, Flag::Draft], Some("S"), Remote), "SRD");
+	// marked as unread locally and read on the server
+	assert_eq!(merge_flags("U", &[Flag::Seen], Some(""), Local), "");
+	assert_eq!(merge_flags("U", &[Flag::Seen], Some(""), Remote), "S");
+	assert_eq!(merge_flags("U", &[Flag::Seen], Some("S"), Remote), "");
+	assert_eq!("remote".parse::<ConflictWinner>().unwrap(), Remote);
+	assert!("server".parse::<ConflictWinner>().is_err());
+}
@@ -0,0 +1,94 @@
+use rusqlite::{Connection, params};
+
+use inboxid_lib::*;
+use inboxid_test_support::test_account;
+
+fn setup(name: &str) -> (Account, Connection) {
+	let account = test_account(name);
+	let db = account.get_db().unwrap();
+	(account, db)
+}
+
+fn mail(n: u32) -> String {
+	format!("Message-ID: <{}@example.com>\r\nSubject: mail {}\r\n\r\nhello\r\n", n, n)
+}
+
+fn store_file(account: &Account, mailbox: &str, id: MaildirID, flags: &str) {
+	account.get_maildir(mailbox).unwrap().store_cur_with_id_flags(&id.to_string(), flags, mail(id.uid).as_bytes()).unwrap();
+}
+
+fn store_row(db: &Connection, mailbox: &str, id: MaildirID, flags: &str) {
+	db.execute("INSERT INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)",
+		params![mailbox, id.uid_validity, id.uid, format!("<{}@example.com>", id.uid), flags]).unwrap();
+}
+
+#[test]
+fn problems_are_found_and_repaired() {
+	let (account, db) = setup("repair");
+	let ok = MaildirID::new(100, 1);
+	store_file(&account, "INBOX", ok, "S");
+	store_row(&db, "INBOX", ok, "S");
+	let missing_file = MaildirID::new(100, 2);
+	store_row(&db, "INBOX", missing_file, "S");
+	let missing_row = MaildirID::new(100, 3);
+	store_file(&account, "INBOX", missing_row, "S");
+	let flags = MaildirID::new(100, 4);
+	store_file(&account, "INBOX", flags, "FS");
+	store_row(&db, "INBOX", flags, "S");
+	let old = MaildirID::new(90, 5);
+	store_file(&account, "INBOX", old, "S");
+	store_row(&db, "INBOX", old, "S");
+	// the same mail stored twice
+	let inbox = account.get_maildir("INBOX").unwrap();
+	inbox.store_new_with_id(&ok.to_string(), mail(1).as_bytes()).unwrap();
+	// an interrupted removal, the file in .gone is a hardlink
+	let gone = account.get_maildir(GONE).unwrap();
+	let removed = MaildirID::new(100, 6);
+	store_file(&account, "INBOX", removed, "S");
+	store_row(&db, "INBOX", removed, "S");
+	maildir_cp(&inbox, &gone, &removed.to_string(), &removed.to_string(), "", true).unwrap();
+	// a copy with the same ID, not a hardlink of the stored mail
+	let other = MaildirID::new(100, 7);
+	store_file(&account, "INBOX", other, "S");
+	store_row(&db, "INBOX", other, "S");
+	gone.store_new_with_id(&other.to_string(), mail(7).as_bytes()).unwrap();
+
+	let mut problems = check_mailbox(&db, &account, "INBOX", Some(100)).unwrap();
+	problems.extend(check_gone(&db, &account).unwrap());
+	let summary = problems.iter().map(|x| match x {
+		Problem::MissingFile { id, .. } => ("missing file", *id),
+		Problem::MissingRow { id, .. } => ("missing row", *id),
+		Problem::FlagMismatch { id, .. } => ("flags", *id),
+		Problem::DuplicateFiles { id, .. } => ("duplicate", *id),
+		Problem::UidValidityMismatch { id, .. } => ("uid validity", *id),
+		Problem::GoneOrphan { id, .. } => ("gone", *id),
+	}).collect::<Vec<_>>();
+	assert_eq!(summary, vec![
+		("uid validity", old),
+		("duplicate", ok),
+		("missing file", missing_file),
+		("missing row", missing_row),
+		("flags", flags),
+		("gone", removed),
+	]);
+
+	for problem in &problems {
+		problem.repair(&db, &account).unwrap();
+	}
+	assert!(check_mailbox(&db, &account, "INBOX", Some(100)).unwrap().is_empty());
+	assert!(check_gone(&db, &account).unwrap().is_empty());
+	assert!(inbox.find_filename(&removed.to_string()).is_some());
+	assert!(gone.find_filename(&other.to_string()).is_some());
+	assert!(account.get_maildir(GONE).unwrap().find_filename(&old.to_string()).is_some());
+	let flags: String = db.query_row("SELECT flags FROM mail WHERE uid = 4", params![], |row| row.get(0)).unwrap();
+	assert_eq!(flags, "FS");
+}
+
+#[test]
+fn consistent_mailbox_has_no_problems() {
+	let (account, db) = setup("consistent");
+	store_file(&account, "INBOX", MaildirID::new(100, 1), "RS");
+	store_row(&db, "INBOX", MaildirID::new(100, 1), "SR");
+	assert!(check_mailbox(&db, &account, "INBOX", Some(100)).unwrap().is_empty());
+	assert!(check_mailbox(&db, &account, "Archive", None).unwrap().is_empty());
+}
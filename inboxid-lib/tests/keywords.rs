@@ -0,0 +1,35 @@
+use imap::types::Flag;
+
+use inboxid_lib::*;
+use inboxid_test_support::test_account;
+
+fn tags(x: &[&str]) -> Vec<String> {
+	x.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn flags_are_mapped() {
+	let flags = vec![Flag::Seen, Flag::Draft, Flag::Custom("$Forwarded".into()), Flag::Deleted];
+	assert_eq!(imap_flags_to_maildir("FRSa".to_owned(), &flags), "aSDP");
+	assert_eq!(maildir_flags_to_imap("DFPSa"), vec![Flag::Draft, Flag::Flagged, Flag::Custom("$Forwarded".into()), Flag::Seen]);
+}
+
+#[test]
+fn keyword_letters_are_stored_in_a_table() {
+	let account = test_account("keywords");
+	let maildir = account.get_maildir("INBOX").unwrap();
+
+	let mut table = KeywordTable::load(&maildir).unwrap();
+	assert_eq!(table.letters(&tags(&["work", "$Junk"])), "ab");
+	assert_eq!(table.letters(&tags(&["todo", "WORK"])), "ca");
+	table.save(&maildir).unwrap();
+	assert_eq!(maildir.get_file(KEYWORDS_FILE).unwrap(), "0 work\n1 $Junk\n2 todo\n");
+
+	// tables written by Dovecot may have gaps
+	maildir.save_file(KEYWORDS_FILE, "0 work\n3 $Junk\ninvalid\n").unwrap();
+	let mut table = KeywordTable::load(&maildir).unwrap();
+	assert_eq!(table.keywords("FSdaz"), tags(&["$Junk", "work"]));
+	assert_eq!(table.letters(&tags(&["todo"])), "b");
+	let many = (0..30).map(|x| format!("k{}", x)).collect::<Vec<_>>();
+	assert_eq!(table.letters(&many).len(), 23);
+}
@@ -0,0 +1,60 @@
+use rusqlite::params;
+
+use inboxid_lib::*;
+use inboxid_test_support::test_account;
+
+#[test]
+fn modified_utf7_is_decoded() {
+	assert_eq!(decode_mailbox_name("Entw&APw-rfe").unwrap(), "Entwürfe");
+	assert_eq!(decode_mailbox_name("~peter/mail/&U,BTFw-/&ZeVnLIqe-").unwrap(), "~peter/mail/台北/日本語");
+	assert_eq!(decode_mailbox_name("Tom &- Jerry").unwrap(), "Tom & Jerry");
+	assert!(decode_mailbox_name("&AB").is_err());
+	assert!(decode_mailbox_name("&A.B-").is_err());
+}
+
+#[test]
+fn hierarchy_is_mapped_to_directories() {
+	assert_eq!(local_mailbox_name("INBOX", Some(".")), "INBOX");
+	assert_eq!(local_mailbox_name("INBOX.Lists.rust", Some(".")), "INBOX/Lists/rust");
+	assert_eq!(local_mailbox_name("Archives/2021", Some("/")), "Archives/2021");
+	assert_eq!(local_mailbox_name("Archives/2021", None), "Archives%2F2021");
+	assert_eq!(local_mailbox_name("Entw&APw-rfe.cur", Some(".")), "Entwürfe/%63ur");
+	assert_eq!(local_mailbox_name(".hidden", Some("/")), "%2Ehidden");
+	assert_eq!(local_mailbox_name("100%", Some("/")), "100%25");
+	assert_eq!(unescape_mailbox_level("%63ur"), "cur");
+	assert_eq!(unescape_mailbox_level("100%25"), "100%");
+}
+
+#[test]
+fn nested_maildirs_are_listed() {
+	let account = test_account("nested");
+	for mailbox in &["INBOX", "INBOX/Lists/rust", "Archives/2021", GONE] {
+		account.get_maildir(mailbox).unwrap();
+	}
+	let mut maildirs = account.get_maildirs().unwrap();
+	maildirs.sort_unstable();
+	assert_eq!(maildirs, vec!["Archives/2021", "INBOX", "INBOX/Lists/rust"]);
+}
+
+#[test]
+fn mailboxes_are_renamed() {
+	let account = test_account("rename");
+	let db = account.get_db().unwrap();
+	let id = MaildirID::new(100, 1);
+	account.get_maildir("INBOX.Lists").unwrap().store_cur_with_id_flags(&id.to_string(), "S", b"Subject: hi\r\n\r\n").unwrap();
+	db.execute("INSERT INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)",
+		params!["INBOX.Lists", id.uid_validity, id.uid, "<a@example.com>", "S"]).unwrap();
+
+	rename_mailbox(&db, &account, "INBOX.Lists", "INBOX/Lists").unwrap();
+
+	assert!(account.get_maildir("INBOX/Lists").unwrap().find_filename(&id.to_string()).is_some());
+	assert_eq!(account.get_maildirs().unwrap(), vec!["INBOX/Lists"]);
+	let mailbox: String = db.query_row("SELECT mailbox FROM mail", params![], |row| row.get(0)).unwrap();
+	assert_eq!(mailbox, "INBOX/Lists");
+
+	// both directories exist: nothing is moved
+	account.get_maildir("Archive").unwrap();
+	assert!(rename_mailbox(&db, &account, "INBOX/Lists", "Archive").is_err());
+	let mailbox: String = db.query_row("SELECT mailbox FROM mail", params![], |row| row.get(0)).unwrap();
+	assert_eq!(mailbox, "INBOX/Lists");
+}
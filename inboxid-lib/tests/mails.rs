@@ -0,0 +1,73 @@
+use inboxid_lib::*;
+use inboxid_test_support::test_account;
+
+const MAIL: &str = "Message-ID: <a@example.com>\r\nFrom: Alice <alice@example.com>\r\nSubject: hello\r\nDate: Mon, 1 Feb 2021 09:00:00 +0000\r\n\r\nSubject: not a header\r\n";
+
+#[test]
+fn mails_are_listed_lazily() {
+	let account = test_account("lazy");
+	let maildir = account.get_maildir("INBOX").unwrap();
+	maildir.store_cur_with_id_flags("100_1", "S", MAIL.as_bytes()).unwrap();
+	maildir.store_new_with_id("100_2", b"Subject: new\n\nbody\n").unwrap();
+
+	let mut mails = maildir.mails().collect::<Result<Vec<_>>>().unwrap();
+	mails.sort_by_key(|x| x.id);
+	assert_eq!(mails.len(), 2);
+	let mail = &mails[0];
+	assert_eq!((mail.id, mail.new, &*mail.get_flags()), (MaildirID::new(100, 1), false, "S"));
+	assert_eq!(mail.subject, "hello");
+	assert_eq!(mail.message_id, "<a@example.com>");
+	assert_eq!(mail.date.timestamp(), 1612170000);
+	assert!(!mail.is_loaded());
+	assert_eq!(mail.parsed().unwrap().get_body().unwrap(), "Subject: not a header\r\n");
+	assert!(mail.is_loaded());
+	assert_eq!((mails[1].new, &*mails[1].subject), (true, "new"));
+
+	let new = maildir.mails().only_new().collect::<Result<Vec<_>>>().unwrap();
+	assert_eq!(new.iter().map(|x| x.id).collect::<Vec<_>>(), vec![MaildirID::new(100, 2)]);
+	assert_eq!(new[0].get_header_values("Subject").unwrap(), vec!["new"]);
+	let found = maildir.find_mail(MaildirID::new(100, 2)).unwrap();
+	assert_eq!((found.new, &*found.subject), (true, "new"));
+	assert!(maildir.find_mail(MaildirID::new(100, 3)).is_err());
+
+	// the mails own their data, the maildir can be listed again after they are dropped
+	drop(mails);
+	assert_eq!(maildir.mails().count(), 2);
+}
+
+#[test]
+fn invalid_dates_fall_back() {
+	let account = test_account("dates");
+	let maildir = account.get_maildir("INBOX").unwrap();
+	maildir.store_cur_with_id_flags("100_1", "S", b"Date: yesterday\r\nReceived: from a by b; Mon, 1 Feb 2021 09:00:00 +0000\r\nSubject: spam\r\n\r\n").unwrap();
+	maildir.store_cur_with_id_flags("100_2", "S", b"Date: yesterday\r\nSubject: spam\r\n\r\n").unwrap();
+
+	let mut mails = maildir.mails().collect::<Result<Vec<_>>>().unwrap();
+	mails.sort_by_key(|x| x.id);
+	assert_eq!((mails[0].date.timestamp(), mails[0].date_source), (1612170000, DateSource::Received));
+	assert!(mails[0].date_iso.ends_with('?'));
+	assert_eq!(mails[1].date_source, DateSource::Mtime);
+	assert!(mails[1].date.timestamp() > 1612170000);
+
+	let headers: &[mailparse::MailHeader] = &[];
+	assert_eq!(mail_date(headers, None), (0, DateSource::Epoch));
+}
+
+#[test]
+fn parallel_loading_is_sorted() {
+	let account = test_account("parallel");
+	let maildir = account.get_maildir("INBOX").unwrap();
+	for uid in (1..=30).rev() {
+		let mail = format!("Subject: mail {}\r\n\r\n", uid);
+		if uid % 3 == 0 {
+			maildir.store_new_with_id(&format!("100_{}", uid), mail.as_bytes()).unwrap();
+		} else {
+			maildir.store_cur_with_id_flags(&format!("100_{}", uid), "S", mail.as_bytes()).unwrap();
+		}
+	}
+
+	let mails = maildir.mails().collect_parallel().unwrap();
+	assert_eq!(mails.iter().map(|x| x.id.uid).collect::<Vec<_>>(), (1..=30).collect::<Vec<_>>());
+	assert!(mails.iter().all(|x| x.subject == format!("mail {}", x.id.uid) && x.new == (x.id.uid % 3 == 0)));
+	assert_eq!(maildir.mails().only_new().collect_parallel().unwrap().len(), 10);
+}
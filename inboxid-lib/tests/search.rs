@@ -0,0 +1,63 @@
+use mailparse::parse_mail;
+use rusqlite::Connection;
+
+use inboxid_lib::*;
+use inboxid_test_support::test_account;
+
+const PLAIN: &str = "From: Alice <alice@example.com>\r\n\
+	To: bob@example.com\r\n\
+	Subject: Lunch\r\n\
+	\r\n\
+	Shall we meet at the pizzeria?\r\n";
+
+const ALTERNATIVE: &str = "From: Carol <carol@example.com>\r\n\
+	To: bob@example.com\r\n\
+	Subject: Newsletter\r\n\
+	Content-Type: multipart/alternative; boundary=\"b\"\r\n\
+	\r\n\
+	--b\r\n\
+	Content-Type: text/html\r\n\
+	\r\n\
+	<p>Our <b>anniversary</b> sale starts today</p>\r\n\
+	--b--\r\n";
+
+fn hits(db: &Connection, query: &str) -> Vec<(String, MaildirID)> {
+	search(db, query, 10).unwrap().into_iter().map(|x| (x.mailbox, x.id)).collect()
+}
+
+#[test]
+fn index_and_search() {
+	let db = test_account("index").get_db().unwrap();
+	let plain = MaildirID::new(100, 1);
+	let html = MaildirID::new(100, 2);
+	index_mail(&db, "INBOX", plain, &parse_mail(PLAIN.as_bytes()).unwrap()).unwrap();
+	index_mail(&db, "INBOX", html, &parse_mail(ALTERNATIVE.as_bytes()).unwrap()).unwrap();
+
+	assert_eq!(hits(&db, "pizzeria"), vec![("INBOX".to_owned(), plain)]);
+	assert_eq!(hits(&db, "anniversary"), vec![("INBOX".to_owned(), html)]);
+	assert_eq!(hits(&db, "sender:carol"), vec![("INBOX".to_owned(), html)]);
+	assert_eq!(hits(&db, "recipients:bob").len(), 2);
+
+	let hit = &search(&db, "pizzeria", 10).unwrap()[0];
+	assert_eq!(hit.subject, "Lunch");
+	assert!(hit.snippet.contains("[pizzeria]"));
+}
+
+#[test]
+fn reindex_copy_and_remove() {
+	let db = test_account("remove").get_db().unwrap();
+	let id = MaildirID::new(100, 1);
+	let mail = parse_mail(PLAIN.as_bytes()).unwrap();
+	index_mail(&db, "INBOX", id, &mail).unwrap();
+	// indexing again replaces the old entry
+	index_mail(&db, "INBOX", id, &mail).unwrap();
+	assert_eq!(hits(&db, "pizzeria").len(), 1);
+
+	copy_index(&db, "INBOX", id, "Archive", MaildirID::new(300, 5)).unwrap();
+	assert_eq!(hits(&db, "pizzeria").len(), 2);
+
+	unindex_mail(&db, "INBOX", id).unwrap();
+	assert_eq!(hits(&db, "pizzeria"), vec![("Archive".to_owned(), MaildirID::new(300, 5))]);
+	unindex_mailbox(&db, "Archive").unwrap();
+	assert!(hits(&db, "pizzeria").is_empty());
+}
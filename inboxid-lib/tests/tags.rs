@@ -0,0 +1,50 @@
+use imap::types::Flag;
+use rusqlite::params;
+
+use inboxid_lib::*;
+use inboxid_test_support::test_account;
+
+fn tags(x: &[&str]) -> Vec<String> {
+	x.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn tags_are_stored_by_message_id() {
+	let db = test_account("store").get_db().unwrap();
+	db.execute("INSERT INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)",
+		params!["INBOX", 100, 1, "<a@example.com>", "S"]).unwrap();
+	db.execute("INSERT INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)",
+		params!["Archive", 200, 5, "<a@example.com>", "S"]).unwrap();
+	add_tag(&db, "<a@example.com>", "work").unwrap();
+	add_tag(&db, "<a@example.com>", "$Todo").unwrap();
+	add_tag(&db, "<a@example.com>", "work").unwrap();
+	assert!(add_tag(&db, "<a@example.com>", "two words").is_err());
+	assert!(add_tag(&db, "<a@example.com>", "\\Seen").is_err());
+
+	assert_eq!(get_tags(&db, "<a@example.com>").unwrap(), tags(&["$Todo", "work"]));
+	assert_eq!(all_tags(&db).unwrap(), tags(&["$Todo", "work"]));
+	assert_eq!(tagged_mails(&db, "work").unwrap(), vec![
+		("Archive".to_owned(), MaildirID::new(200, 5)),
+		("INBOX".to_owned(), MaildirID::new(100, 1)),
+	]);
+
+	remove_tag(&db, "<a@example.com>", "work").unwrap();
+	assert!(tagged_mails(&db, "work").unwrap().is_empty());
+}
+
+#[test]
+fn changes_are_merged() {
+	let local = tags(&["kept", "added-locally", "removed-remotely"]);
+	let remote = tags(&["kept", "added-remotely", "removed-locally"]);
+	let base = tags(&["kept", "removed-locally", "removed-remotely"]);
+	assert_eq!(merge_tags(&local, &remote, &base), tags(&["added-locally", "added-remotely", "kept"]));
+}
+
+#[test]
+fn keywords_are_custom_flags() {
+	let flags = vec![Flag::Seen, Flag::Custom("$Junk".into()), Flag::Recent, Flag::Custom("work".into())];
+	assert_eq!(keywords(&flags), tags(&["$Junk", "work"]));
+	// stored as maildir flag
+	assert!(keywords(&[Flag::Custom("$Forwarded".into())]).is_empty());
+	assert!(!is_valid_tag("$forwarded"));
+}
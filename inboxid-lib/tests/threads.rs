@@ -0,0 +1,80 @@
+use rusqlite::{Connection, params};
+
+use inboxid_lib::*;
+use inboxid_test_support::test_account;
+
+/// Store a mail like inboxid-sync does.
+fn store(db: &Connection, mailbox: &str, id: MaildirID, message_id: &str, in_reply_to: &str, references: &str) {
+	db.execute("INSERT INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)",
+		params![mailbox, id.uid_validity, id.uid, message_id, "S"]).unwrap();
+	save_summary(db, mailbox, &MailSummary {
+		id,
+		flags: "S".to_owned(),
+		message_id: message_id.to_owned(),
+		subject: String::new(),
+		from: String::new(),
+		date: id.uid as i64,
+		date_source: DateSource::Header,
+		in_reply_to: in_reply_to.to_owned(),
+		references: references.to_owned(),
+		size: 0,
+		has_attachment: false,
+		new: false,
+	}).unwrap();
+}
+
+#[test]
+fn threads_span_mailboxes() {
+	let db = test_account("span").get_db().unwrap();
+	let reply = MaildirID::new(100, 1);
+	let other = MaildirID::new(100, 2);
+	let parent = MaildirID::new(200, 3);
+	store(&db, "INBOX", reply, "<b@example.com>", "<a@example.com>", "");
+	store(&db, "INBOX", other, "<c@example.com>", "", "");
+	let thread = thread_of(&db, "INBOX", reply).unwrap().unwrap();
+	assert_ne!(thread_of(&db, "INBOX", other).unwrap(), Some(thread));
+
+	// the parent arrives later, in another mailbox
+	store(&db, "Sent", parent, "<a@example.com>", "", "");
+	assert_eq!(thread_of(&db, "Sent", parent).unwrap(), Some(thread));
+	assert_eq!(messages_in_thread(&db, thread).unwrap(), vec![
+		("INBOX".to_owned(), reply),
+		("Sent".to_owned(), parent),
+	]);
+	assert_eq!(threads_of_mailbox(&db, "Sent").unwrap(), vec![
+		(thread, "INBOX".to_owned(), reply, "<b@example.com>".to_owned()),
+		(thread, "Sent".to_owned(), parent, "<a@example.com>".to_owned()),
+	]);
+	assert_eq!(threads_of_mailbox(&db, "INBOX").unwrap().len(), 3);
+}
+
+#[test]
+fn threads_are_merged() {
+	let db = test_account("merge").get_db().unwrap();
+	store(&db, "INBOX", MaildirID::new(100, 1), "<a@example.com>", "", "");
+	store(&db, "INBOX", MaildirID::new(100, 2), "<b@example.com>", "", "");
+	// a mail referencing both connects the threads
+	store(&db, "Sent", MaildirID::new(200, 3), "<c@example.com>", "<b@example.com>", "<a@example.com> <b@example.com>");
+
+	let thread = thread_of(&db, "INBOX", MaildirID::new(100, 2)).unwrap().unwrap();
+	assert_eq!(thread_of(&db, "INBOX", MaildirID::new(100, 1)).unwrap(), Some(thread));
+	assert_eq!(messages_in_thread(&db, thread).unwrap().len(), 3);
+	assert_eq!(thread_of(&db, "INBOX", MaildirID::new(100, 4)).unwrap(), None);
+}
+
+#[test]
+fn uncached_mails_are_parsed() {
+	let account = test_account("uncached");
+	let db = account.get_db().unwrap();
+	let maildir = account.get_maildir("Sent").unwrap();
+	let id = MaildirID::new(200, 1);
+	maildir.store_cur_with_id_flags(&id.to_string(), "S", b"Message-ID: <a@example.com>\r\nSubject: hi\r\n\r\nhello\r\n").unwrap();
+	db.execute("INSERT INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)",
+		params!["Sent", id.uid_validity, id.uid, "<a@example.com>", "S"]).unwrap();
+	assert_eq!(thread_of(&db, "Sent", id).unwrap(), None);
+
+	let summary = load_summary(&db, "Sent", &maildir, id).unwrap().unwrap();
+	assert_eq!(summary.subject, "hi");
+	assert!(thread_of(&db, "Sent", id).unwrap().is_some());
+	assert_eq!(load_summary(&db, "Sent", &maildir, MaildirID::new(200, 2)).unwrap(), None);
+}
@@ -1,13 +1,12 @@
-use std::{array::IntoIter, collections::HashSet, env, fs};
+use std::{array::IntoIter, collections::HashSet, fs, rc::Rc};
 
 use ascii_table::{Align, AsciiTable, Column};
 use inboxid_lib::*;
 use itertools::Itertools;
-use mailparse::ParsedMail;
 use rustyline::{Editor, error::ReadlineError};
 
 fn main() -> Result<()> {
-	let args = env::args().collect_vec();
+	let args = get_args()?;
 	if args.len() > 1 {
 		show_listing(&args[1])
 	} else {
@@ -17,12 +16,9 @@ fn main() -> Result<()> {
 
 fn show_listing(mailbox: &str) -> Result<()> {
 	let maildir = get_maildir(mailbox)?;
+	let db = get_db()?;
 
-	let mut mails = Vec::new();
-	for x in maildir.list_cur() {
-		mails.push(x?);
-	}
-	let mut mails = maildir.get_mails(&mut mails)?;
+	let mut mails = load_summaries(&db, mailbox, &maildir)?.into_iter().map(EasyMail::from).collect_vec();
 	mails.sort_by_key(|x| x.date);
 	
 	let mut rows = Vec::new();
@@ -35,6 +31,9 @@ fn show_listing(mailbox: &str) -> Result<()> {
 		if flags.contains('R') {
 			flags_display.push('R');
 		}
+		if mail.new {
+			flags_display.push('N');
+		}
 		if flags.contains('S') {
 			flags_display.push(' ');
 		} else {
@@ -61,6 +60,11 @@ fn show_listing(mailbox: &str) -> Result<()> {
 	}
 	ascii_table.print(rows); // prints a 0 if empty :)
 
+	// only after the user saw the new mail, move it out of 'new'
+	for seen in mails.iter().filter(|x| x.new) {
+		maildir.move_new_to_cur(&seen.id.to_string())?;
+	}
+
 	if mails.is_empty() {
 		return Ok(());
 	}
@@ -70,40 +74,41 @@ fn show_listing(mailbox: &str) -> Result<()> {
 	loop {
 		let readline = rl.readline(&match state {
 			Initial => ">> ".to_owned(),
-			MailSelected(x) => format!("{} >> ", mails.len() - x),
+			MailSelected(x, _) => format!("{} >> ", mails.len() - x),
 			AwaitingSave(_, _) => "out? >> ".to_owned()
 		});
 		match readline {
 			Ok(line) => {
 				let input_idx = line.trim().parse::<usize>();
-				match state {
+				match state.clone() {
 					Initial => {
 						if let Ok(idx) = input_idx {
 							let idx = mails.len() - idx;
-							let mail = &mails[idx];
-							if mail.ctype.mimetype.starts_with("text/") {
-								let raw_body = mail.get_body_raw();
+							let mail = Rc::new(maildir.find_mail(mails[idx].id)?);
+							let parsed = mail.parsed()?;
+							if parsed.ctype.mimetype.starts_with("text/") {
+								let raw_body = parsed.get_body_raw();
 								let content = std::str::from_utf8(raw_body.as_deref().unwrap())?;
 								moins::Moins::run(content, None);
-							} else if mail.ctype.mimetype.starts_with("multipart/") {
-								mail.print_tree_structure(0, &mut 1);
-								state = MailSelected(idx);
+							} else if parsed.ctype.mimetype.starts_with("multipart/") {
+								parsed.print_tree_structure(0, &mut 1);
+								state = MailSelected(idx, Rc::clone(&mail));
 							} else {
-								state = AwaitingSave(&*mail, None);
+								state = AwaitingSave(Rc::clone(&mail), None);
 							}
 							continue;
 						}
 					},
-					MailSelected(mail_idx) => {
-						let mail = &mails[mail_idx];
+					MailSelected(mail_idx, mail) => {
 						if let Ok(idx) = input_idx {
-							let part = mail.get_tree_part(&mut 1, idx).unwrap();
+							let parsed = mail.parsed()?;
+							let part = parsed.get_tree_part(&mut 1, idx).unwrap();
 							if part.ctype.mimetype.starts_with("text/") {
 								let raw_body = part.get_body_raw();
 								let content = std::str::from_utf8(raw_body.as_deref().unwrap())?;
 								moins::Moins::run(content, None);
 							} else {
-								state = AwaitingSave(part, Some(mail_idx));
+								state = AwaitingSave(Rc::clone(&mail), Some((mail_idx, idx)));
 							}
 							continue;
 						} else if line.is_empty() {
@@ -111,19 +116,24 @@ fn show_listing(mailbox: &str) -> Result<()> {
 							continue;
 						}
 					},
-					AwaitingSave(mail, idx) => {
+					AwaitingSave(mail, part) => {
 						if line == "open" {
-							let path = if let Some(ext) = mime2ext::mime2ext(&mail.ctype.mimetype) {
+							let parsed = mail.parsed()?;
+							let to_save = match part {
+								Some((_, idx)) => parsed.get_tree_part(&mut 1, idx).unwrap(),
+								None => &parsed
+							};
+							let path = if let Some(ext) = mime2ext::mime2ext(&to_save.ctype.mimetype) {
 								format!("/tmp/mail_content.{}", ext)
 							} else {
 								"/tmp/mail_content".to_owned()
 							};
-							fs::write(&path, &mail.get_body_raw()?)?;
+							fs::write(&path, &to_save.get_body_raw()?)?;
 							let mut p = subprocess::Popen::create(&["xdg-open", &path], Default::default())?;
 							p.wait()?;
 							to_delete.insert(path);
-							state = if let Some(idx) = idx {
-								MailSelected(idx)
+							state = if let Some((idx, _)) = part {
+								MailSelected(idx, Rc::clone(&mail))
 							} else {
 								Initial
 							};
@@ -153,10 +163,13 @@ fn show_listing(mailbox: &str) -> Result<()> {
 	Ok(())
 }
 
-enum State<'a> {
+#[derive(Clone)]
+enum State {
 	Initial,
-	MailSelected(usize),
-	AwaitingSave(&'a ParsedMail<'a>, Option<usize>)
+	/// index and opened mail
+	MailSelected(usize, Rc<EasyMail>),
+	/// mail to save, index of the mail and number of the part if only a part is saved
+	AwaitingSave(Rc<EasyMail>, Option<(usize, usize)>)
 }
 
 use State::*;
@@ -1,12 +1,11 @@
-use std::{array::IntoIter, env};
+use std::array::IntoIter;
 
 use ascii_table::{Align, AsciiTable, Column};
-use itertools::Itertools;
 
 use inboxid_lib::*;
 
 fn main() -> Result<()> {
-	let args = env::args().collect_vec();
+	let args = get_args()?;
 	if args.len() > 1 {
 		show_listing(&args[1])
 	} else {
@@ -17,12 +16,7 @@ fn main() -> Result<()> {
 fn show_listing(mailbox: &str) -> Result<()> {
 	let maildir = get_maildir(mailbox)?;
 
-	let mut mails = Vec::new();
-	for x in maildir.list_new() {
-		mails.push(x?);
-	}
-	let mut mails = maildir.get_mails(&mut mails)?;
-	mails.sort_by_key(|x| x.id);
+	let mails = maildir.mails().only_new().collect_parallel()?;
 
 	let mut rows = Vec::new();
 	for mail in &mails {
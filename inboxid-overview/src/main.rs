@@ -1,22 +1,40 @@
-use std::array::IntoIter;
+use std::{array::IntoIter, collections::{HashMap, HashSet}};
 
 use ascii_table::{AsciiTable, Align, Column};
 use inboxid_lib::*;
+use itertools::Itertools;
 
 fn main() -> Result<()> {
-	let mut dirs = get_maildirs()?;
-	dirs.sort_unstable();
-	let mut rows = vec![];
-	for dir in dirs {
+	get_args()?;
+	let mut unread = HashMap::new();
+	for dir in get_maildirs()? {
 		let maildir = get_maildir(&dir)?;
-		let unread = maildir
+		let count = maildir
 			.list_cur()
 			.map(|x| if x.map(|x| !x.flags().contains(SEEN)).unwrap_or(true) { 1 } else { 0 })
 			.sum::<usize>();
-		if unread > 0 {
-			rows.push(IntoIter::new([dir, unread.to_string()]));
+		if count > 0 {
+			unread.insert(dir, count);
+		}
+	}
+	// show the mailboxes with unread mail and their parents
+	let mut shown = HashSet::new();
+	for dir in unread.keys() {
+		let levels = dir.split('/').collect_vec();
+		for i in 1..=levels.len() {
+			shown.insert(levels[..i].join("/"));
 		}
 	}
+	let mut shown = shown.into_iter().collect_vec();
+	// children directly follow their parent
+	shown.sort_unstable_by(|a, b| a.split('/').cmp(b.split('/')));
+	let mut rows = vec![];
+	for dir in shown {
+		let depth = dir.matches('/').count();
+		let name = unescape_mailbox_level(dir.rsplit('/').next().unwrap());
+		let count = unread.get(&dir).map(ToString::to_string).unwrap_or_default();
+		rows.push(IntoIter::new([format!("{}{}", "  ".repeat(depth), name), count]));
+	}
 	let mut ascii_table = AsciiTable::default();
 	ascii_table.draw_lines = false;
 	ascii_table.max_width = usize::MAX;
@@ -0,0 +1,48 @@
+use std::array::IntoIter;
+
+use ascii_table::{AsciiTable, Align, Column};
+use inboxid_lib::*;
+use itertools::Itertools;
+
+const DEFAULT_LIMIT: u32 = 50;
+
+fn main() -> Result<()> {
+	let mut args = get_args()?.into_iter().skip(1).peekable();
+	let mut limit = DEFAULT_LIMIT;
+	if args.peek().map(|x| x == "--limit").unwrap_or(false) {
+		args.next();
+		limit = args.next().ok_or_else(|| InboxidError::Config("missing limit".to_owned()))?.parse()?;
+	}
+	let query = args.join(" ");
+	if query.is_empty() {
+		println!("usage: inboxid-search [--limit N] <query>");
+		return Ok(());
+	}
+
+	let db = get_db()?;
+	let hits = search(&db, &query, limit)?;
+	let rows = hits.into_iter().map(|hit| IntoIter::new([
+		hit.mailbox,
+		hit.id.to_string(),
+		hit.subject,
+		hit.snippet.split_whitespace().join(" "),
+	])).collect_vec();
+
+	let mut ascii_table = AsciiTable::default();
+	ascii_table.draw_lines = false;
+	ascii_table.max_width = usize::MAX;
+	for (i, &(header, align)) in [
+		("Mailbox", Align::Left),
+		("ID", Align::Left),
+		("Subject", Align::Left),
+		("Match", Align::Left),
+	].iter().enumerate() {
+		let mut column = Column::default();
+		column.header = header.to_owned();
+		column.align = align;
+		column.max_width = usize::MAX;
+		ascii_table.columns.insert(i, column);
+	}
+	ascii_table.print(rows); // prints a 0 if empty :)
+	Ok(())
+}
@@ -1,34 +1,31 @@
-use std::env;
-
 use inboxid_lib::*;
 use itertools::Itertools;
 use rusqlite::params;
 
 fn main() -> Result<()> {
+	let mailboxes = get_args()?.into_iter().skip(1).collect_vec();
 	let mut db = get_db()?;
 	let tx = db.transaction()?;
 	{
 	let mut delete_mail = tx.prepare("DELETE FROM mail WHERE mailbox = ?")?;
-	let mut save_mail = tx.prepare("INSERT INTO mail VALUES (?,?,?,?)")?;
-	let mailboxes = env::args().skip(1).collect_vec();
+	let mut save_mail = tx.prepare("INSERT OR REPLACE INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)")?;
 	for mailbox in mailboxes {
 		println!("reading {}..", mailbox);
 		let maildir = get_maildir(&mailbox)?;
 		delete_mail.execute(params![&mailbox])?;
-		let mut mails = Vec::new();
-		for x in maildir.list_cur() {
-			mails.push(x?);
-		}
-		for x in maildir.list_new() {
-			mails.push(x?);
-		}
+		unindex_mailbox(&tx, &mailbox)?;
+		let mut mails = maildir.mails().collect_parallel()?;
 		println!("acquired {} mails", mails.len());
-		let mut mails = maildir.get_mails(&mut mails)?;
 		mails.sort_by_key(|x| x.date);
 		for mail in mails {
-			let headers = mail.get_headers();
-			let message_id = headers.message_id(&mailbox, mail.id);
-			save_mail.execute(params![&mailbox, mail.id.to_i64(), message_id, mail.get_flags()])?;
+			let size = mail.content()?.len() as u64;
+			let parsed = mail.parsed()?;
+			let message_id = parsed.get_headers().message_id(&mailbox, mail.id);
+			save_mail.execute(params![&mailbox, mail.id.uid_validity, mail.id.uid, message_id, mail.get_flags()])?;
+			let summary = MailSummary::new(&mailbox, mail.id, &mail.get_flags(), &parsed, size, mail.path());
+			save_summary(&tx, &mailbox, &summary)?;
+			harvest_addresses(&tx, &summary, &parsed)?;
+			index_mail(&tx, &mailbox, mail.id, &parsed)?;
 		}
 	}
 	}
@@ -1,25 +1,26 @@
-use std::{collections::HashMap, borrow::Cow, fmt::Display};
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet}, borrow::Cow, convert::TryFrom, fmt::Display, ops::RangeInclusive, str::FromStr};
 
-use anyhow::Context;
-use imap::types::{Flag, NameAttribute};
+use imap::types::{Fetch, Flag, Name, NameAttribute};
 use itertools::Itertools;
 use maildir::Maildir;
 
 use inboxid_lib::*;
-use mailparse::parse_header;
-use rusqlite::{Row, params, types::FromSql};
+use mailparse::{parse_header, parse_mail};
+use rusqlite::{Connection, OptionalExtension, Row, params, types::FromSql};
 
 pub static TRASH: NameAttribute = NameAttribute::Custom(Cow::Borrowed("\\Trash"));
 
+#[derive(Debug, PartialEq)]
 pub enum SyncAction {
 	TrashRemote(String, MaildirID),
 	TrashLocal(String, MaildirID),
 	DeleteRemote(String, MaildirID),
 	DeleteLocal(String, MaildirID),
 	UpdateFlags(String, Vec<(MaildirID, Vec<Flag<'static>>, String)>),
+	UpdateTags(String, Vec<TagUpdate>),
 	Hardlink(String, Vec<(MaildirID, String, Vec<Flag<'static>>)>),
 	Fetch(String, Vec<MaildirID>),
-	RemoveStale(HashMap<String, Vec<(u32, u32, u64)>>)
+	RemoveStale(HashMap<String, Vec<MaildirID>>)
 }
 
 impl SyncAction {
@@ -30,6 +31,7 @@ impl SyncAction {
     		DeleteRemote(mailbox, _) => Some(mailbox),
     		DeleteLocal(mailbox, _) => Some(mailbox),
 			UpdateFlags(mailbox, _) => Some(mailbox),
+			UpdateTags(mailbox, _) => Some(mailbox),
     		Hardlink(mailbox, _) => Some(mailbox),
     		Fetch(mailbox, _) => Some(mailbox),
     		RemoveStale(_) => None,
@@ -39,6 +41,19 @@ impl SyncAction {
 
 use SyncAction::*;
 
+/// Tag changes of one mail on the server.
+#[derive(Debug, PartialEq)]
+pub struct TagUpdate {
+	pub id: MaildirID,
+	pub message_id: String,
+	/// keywords to set on the server
+	pub add: Vec<String>,
+	/// keywords to remove on the server
+	pub remove: Vec<String>,
+	/// the merged tags of the message
+	pub tags: Vec<String>,
+}
+
 impl Display for SyncAction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
@@ -47,6 +62,7 @@ impl Display for SyncAction {
     		DeleteRemote(mailbox, id) => write!(f, "delete remotely: {}/{}\n", mailbox, id)?,
     		DeleteLocal(mailbox, id) => write!(f, "delete locally: {}/{}\n", mailbox, id)?,
 			UpdateFlags(mailbox, _) => write!(f, "updating flags of mail in {}\n", mailbox)?,
+			UpdateTags(mailbox, _) => write!(f, "updating tags of mail in {}\n", mailbox)?,
     		Hardlink(mailbox, id) => write!(f, "hardlink from local: {}/{:?}\n", mailbox, id)?,
     		Fetch(mailbox, id) => write!(f, "fetch: {}/{:?}", mailbox, id)?,
     		RemoveStale(map) => write!(f, "remove stale mail: {:?}", map)?,
@@ -55,83 +71,301 @@ impl Display for SyncAction {
     }
 }
 
+/// Flags of the mails on the server, by mailbox and Message-ID.
+pub type RemoteMails = HashMap<String, RemoteMailbox>;
+/// Flags of the mails in one mailbox on the server, by Message-ID.
+pub type RemoteMailbox = HashMap<String, (u32, u32, MaildirID, Vec<Flag<'static>>)>;
+
+/// Get the mails of a mailbox on the server (`server` is its name on the server).
+/// If the server supports CONDSTORE, only mails changed since the last sync are fetched
+/// and the others are taken from the `remote_mail` table. Otherwise all mails are scanned.
+/// With QRESYNC (enabled by the session), the server reports the mails expunged since the last sync (VANISHED).
+/// The responses of QRESYNC are only read from raw responses, which the imap crate cannot parse.
+fn fetch_remote_mails(
+	imap_session: &mut ReconnectingSession,
+	db: &mut Connection,
+	mailbox: &str,
+	server: &str,
+	condstore: bool
+) -> Result<RemoteMailbox> {
+	let previous = db.query_row("SELECT uid_validity, highest_modseq FROM mailbox_state WHERE mailbox = ?", params![mailbox],
+		|row| Ok((row.get::<_, u32>(0)?, row.get::<_, i64>(1)? as u64))).optional()?;
+	let (uid_validity, highest_modseq, exists, vanished) = if condstore {
+		let (parameters, qresync) = match previous {
+			Some((uid_validity, modseq)) if imap_session.qresync() => (format!("QRESYNC ({} {})", uid_validity, modseq), true),
+			_ => ("CONDSTORE".to_owned(), false)
+		};
+		let resp = imap_session.retry(|s| s.run_command_and_read_response(format!("EXAMINE {} ({})", quote(server), parameters)))
+			.map_err(|e| e.in_mailbox(mailbox))?;
+		let resp = String::from_utf8_lossy(&resp);
+		let uid_validity = response_code(&resp, "UIDVALIDITY");
+		imap_session.set_selected(server, uid_validity, true);
+		let vanished = if qresync { Some(vanished_uids(&resp)) } else { None };
+		// NOMODSEQ mailboxes do not have a HIGHESTMODSEQ
+		(uid_validity, response_code::<u64>(&resp, "HIGHESTMODSEQ"), untagged_count(&resp, "EXISTS"), vanished)
+	} else {
+		let resp = imap_session.examine(server)?;
+		(resp.uid_validity, None, Some(resp.exists as usize), None)
+	};
+	let uid_validity: u32 = uid_validity.ok_or_else(|| InboxidError::imap(mailbox, imap::Error::Bad("no UIDVALIDITY in EXAMINE response".to_owned())))?;
+
+	let tx = db.transaction()?;
+	let query = "(FLAGS BODY[HEADER.FIELDS (MESSAGE-ID)])";
+	let mut mails = BTreeMap::new();
+	match (highest_modseq, previous) {
+		(Some(modseq), Some((prev_uid_validity, prev_modseq))) if prev_uid_validity == uid_validity => {
+			let mut stmt = tx.prepare("SELECT uid, message_id, flags FROM remote_mail WHERE mailbox = ?")?;
+			for x in stmt.query_map(params![mailbox], map3rows::<u32, String, String>)? {
+				let (uid, message_id, flags) = x?;
+				mails.insert(uid, (message_id, parse_flags(&flags)));
+			}
+			for range in vanished.iter().flatten() {
+				for uid in mails.keys().filter(|x| range.contains(*x)) {
+					tx.execute("DELETE FROM remote_mail WHERE mailbox = ? AND uid = ?", params![mailbox, uid])?;
+				}
+				mails.retain(|uid, _| !range.contains(uid));
+			}
+			if modseq != prev_modseq {
+				println!("fetching changes since modseq {}", prev_modseq);
+				let messages = imap_session.retry(|s| s.uid_fetch("1:*", format!("{} (CHANGEDSINCE {})", query, prev_modseq)))?;
+				for m in messages.iter() {
+					let (uid, message_id, flags) = remote_entry(mailbox, uid_validity, m);
+					tx.execute("INSERT OR REPLACE INTO remote_mail VALUES (?,?,?,?)", params![mailbox, uid, message_id, flags.iter().join(" ")])?;
+					mails.insert(uid, (message_id, flags));
+				}
+			}
+			// every mail still on the server is known now, additional ones were expunged
+			// (without QRESYNC, or if the server did not report all of them)
+			if exists != Some(mails.len()) {
+				println!("checking for expunged mail");
+				let uids = imap_session.retry(|s| s.uid_search("ALL"))?;
+				for uid in mails.keys().filter(|&x| !uids.contains(x)) {
+					tx.execute("DELETE FROM remote_mail WHERE mailbox = ? AND uid = ?", params![mailbox, uid])?;
+				}
+				mails.retain(|uid, _| uids.contains(uid));
+			}
+		},
+		_ => {
+			tx.execute("DELETE FROM remote_mail WHERE mailbox = ?", params![mailbox])?;
+			let messages = imap_session.retry(|s| s.uid_fetch("1:*", query))?;
+			for m in messages.iter() {
+				let (uid, message_id, flags) = remote_entry(mailbox, uid_validity, m);
+				if highest_modseq.is_some() {
+					tx.execute("INSERT INTO remote_mail VALUES (?,?,?,?)", params![mailbox, uid, message_id, flags.iter().join(" ")])?;
+				}
+				mails.insert(uid, (message_id, flags));
+			}
+		}
+	}
+	match highest_modseq {
+		Some(modseq) => tx.execute("INSERT OR REPLACE INTO mailbox_state VALUES (?,?,?)", params![mailbox, uid_validity, modseq as i64])?,
+		None => tx.execute("DELETE FROM mailbox_state WHERE mailbox = ?", params![mailbox])?,
+	};
+	tx.commit()?;
+
+	let mut remote = HashMap::new();
+	for (uid, (message_id, flags)) in mails {
+		if flags.contains(&Flag::Deleted) {
+			continue;
+		}
+		remote.insert(message_id, (uid_validity, uid, MaildirID::new(uid_validity, uid), flags));
+	}
+	Ok(remote)
+}
+
+fn remote_entry(mailbox: &str, uid_validity: u32, m: &Fetch) -> (u32, String, Vec<Flag<'static>>) {
+	let uid = m.uid.unwrap();
+	let mut message_id = m.header().and_then(|x| parse_header(x).ok()).map(|x| x.0.get_value()).unwrap_or_default();
+	if message_id.is_empty() {
+		message_id = fallback_mid(mailbox, MaildirID::new(uid_validity, uid));
+	}
+	let flags = m.flags().iter().map(|x| remove_cow(x)).collect_vec();
+	(uid, message_id, flags)
+}
+
+fn parse_flags(flags: &str) -> Vec<Flag<'static>> {
+	flags.split_whitespace().map(|x| Flag::from(x.to_owned())).collect()
+}
+
+/// Find the keywords changed by other maildir clients, by Message-ID (added and removed keywords).
+/// The letters in the filenames are compared with the flags in the mail table, which sync keeps up to date.
+fn keyword_edits<'a>(
+	db: &Connection,
+	account: &Account,
+	mailboxes: impl Iterator<Item = &'a String>
+) -> Result<HashMap<String, (BTreeSet<String>, BTreeSet<String>)>> {
+	let mut edits: HashMap<_, (BTreeSet<_>, BTreeSet<_>)> = HashMap::new();
+	let mut stmt = db.prepare("SELECT uid_validity, uid, message_id, flags FROM mail WHERE mailbox = ?")?;
+	for mailbox in mailboxes {
+		let maildir = account.get_maildir(mailbox)?;
+		let table = KeywordTable::load(&maildir)?;
+		let rows = stmt.query_map(params![mailbox], |row| Ok((MaildirID::from_row(row, 0)?, (row.get::<_, String>(2)?, row.get::<_, String>(3)?))))?
+			.collect::<rusqlite::Result<HashMap<_, _>>>()?;
+		for entry in maildir.list_cur() {
+			let entry = entry?;
+			let (message_id, flags) = match MaildirID::try_from(entry.id()).ok().and_then(|x| rows.get(&x)) {
+				Some(x) => x,
+				None => continue
+			};
+			let file = table.keywords(entry.flags());
+			let stored = table.keywords(flags);
+			if file != stored {
+				let (added, removed) = edits.entry(message_id.clone()).or_default();
+				added.extend(file.iter().filter(|x| !stored.contains(x)).cloned());
+				removed.extend(stored.iter().filter(|x| !file.contains(x)).cloned());
+			}
+		}
+	}
+	Ok(edits)
+}
+
+/// Get the local names of the mailboxes on the server, by server name (see [local_mailbox_name]).
+fn local_names(names: &[&Name]) -> HashMap<String, String> {
+	names.iter().map(|x| (x.name().to_owned(), local_mailbox_name(x.name(), x.delimiter()))).collect()
+}
+
+/// Move the mailboxes stored under their server name by older versions to their local name.
+/// [compute_sync_actions] does not change anything, call this before it unless doing a dry run.
+pub fn migrate_mailbox_names(account: &Account) -> Result<()> {
+	let db = account.get_db()?;
+	let mut imap_session = ReconnectingSession::connect(account)?;
+	let list = imap_session.retry(|s| s.list(None, Some("*")))?;
+	let local = local_names(&list.iter().collect_vec());
+	let taken = local.values().collect::<HashSet<_>>();
+	for (server, name) in &local {
+		// the old location may already be the new location of another mailbox
+		if server != name && !taken.contains(server) {
+			rename_mailbox(&db, account, server, name)?;
+		}
+	}
+	imap_session.logout()?;
+	Ok(())
+}
+
+/// Quote a mailbox name for a raw IMAP command.
+fn quote(mailbox: &str) -> String {
+	format!("\"{}\"", mailbox.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Find the value of a response code like `[HIGHESTMODSEQ 42]` in a raw response.
+fn response_code<T: FromStr>(resp: &str, code: &str) -> Option<T> {
+	let start = resp.find(&format!("[{} ", code))? + code.len() + 2;
+	resp[start..].split(']').next()?.trim().parse().ok()
+}
+
+/// Find the UIDs reported by `* VANISHED (EARLIER) 1:3,7` responses in a raw response, as ranges.
+fn vanished_uids(resp: &str) -> Vec<RangeInclusive<u32>> {
+	let mut ranges = Vec::new();
+	for line in resp.lines() {
+		let set = match line.strip_prefix("* VANISHED ") {
+			Some(x) => x.trim_start_matches("(EARLIER)").trim(),
+			None => continue
+		};
+		for range in set.split(',') {
+			let mut parts = range.splitn(2, ':').map(str::parse::<u32>);
+			match (parts.next(), parts.next()) {
+				(Some(Ok(a)), None) => ranges.push(a..=a),
+				(Some(Ok(a)), Some(Ok(b))) => ranges.push(a.min(b)..=a.max(b)),
+				_ => {}
+			}
+		}
+	}
+	ranges
+}
+
+/// Find the number of an untagged response like `* 42 EXISTS` in a raw response.
+fn untagged_count(resp: &str, name: &str) -> Option<usize> {
+	resp.lines().find_map(|line| {
+		let mut words = line.split_whitespace();
+		match (words.next(), words.next(), words.next()) {
+			(Some("*"), Some(n), Some(x)) if x.eq_ignore_ascii_case(name) => n.parse().ok(),
+			_ => None
+		}
+	})
+}
+
 pub fn compute_sync_actions(
-	host: &str,
-	user: &str,
-	password: &str,
-	port: u16,
+	account: &Account,
 	mailboxes: &[String]
-) -> Result<(Vec<SyncAction>, HashMap<String, HashMap<String, (u32, u32, MaildirID, Vec<Flag<'static>>)>>)> {
+) -> Result<(Vec<SyncAction>, RemoteMails)> {
 	let mut actions = Vec::new();
 
-	let mut db = get_db()?;
-	let mut imap_session = connect(host, port, user, password)?;
+	let mut db = account.get_db()?;
+	let mut imap_session = ReconnectingSession::connect_qresync(account)?;
 	println!("getting capabilities..");
-	let caps = imap_session.capabilities()?;
+	let caps = imap_session.retry(|s| s.capabilities())?;
 	println!("capabilities: {}", caps.iter().map(|x| format!("{:?}", x)).join(" "));
+	let condstore = caps.has_str("CONDSTORE") || caps.has_str("QRESYNC");
 
 	let mut names = Vec::new();
-	let list = imap_session.list(None, Some("*"))?;
+	let list = imap_session.retry(|s| s.list(None, Some("*")))?;
 	for x in list.iter() {
 		println!("{:?}", x);
 		names.push(x);
 	}
+	let local = local_names(&names);
 
 	let mut remote = HashMap::new();
 
 	for &name in &names {
-		let mailbox = name.name();
+		let mailbox = &*local[name.name()];
 		// if the user specified some mailboxes, only process those
-		if !mailboxes.is_empty() && !mailboxes.iter().any(|x| x == mailbox) {
+		if !mailboxes.is_empty() && !mailboxes.iter().any(|x| x == mailbox || x == name.name()) {
 			continue;
 		}
 		println!("indexing {}", mailbox);
-		let resp = imap_session.examine(mailbox)?;
-		let uid_validity = resp.uid_validity.unwrap();
-
-		let mut mails = HashMap::new();
-		let messages = imap_session.uid_fetch("1:*", "(FLAGS BODY[HEADER.FIELDS (MESSAGE-ID)])")?;
-		for m in messages.iter() {
-			let id = MaildirID::new(uid_validity, m.uid.unwrap());
-			let flags = m.flags();
-			if flags.contains(&Flag::Deleted) {
-				continue;
-			}
-			let header = m.header().unwrap();
-			let mut message_id = parse_header(header).map(|x| x.0.get_value()).unwrap_or_default();
-			if message_id.is_empty() {
-				message_id = fallback_mid(mailbox, id);
-			}
-			let flags = flags.iter().map(|x| remove_cow(x)).collect_vec();
-			mails.insert(message_id, (id.uid_validity, id.uid, id, flags));
-		}
+		let mails = fetch_remote_mails(&mut imap_session, &mut db, mailbox, name.name(), condstore)?;
 		remote.insert(mailbox.to_string(), mails);
 	}
 
+	// a keyword is set remotely if any copy of the message on the server has it
+	let mut remote_tags: HashMap<String, BTreeSet<String>> = HashMap::new();
+	for mails in remote.values() {
+		for (message_id, (_, _, _, flags)) in mails {
+			remote_tags.entry(message_id.clone()).or_default().extend(keywords(flags));
+		}
+	}
+	let keyword_edits = keyword_edits(&db, account, remote.keys())?;
+	// merged tags of every message, and whether the local tags have to be updated
+	let mut merged_tags = HashMap::new();
+	for (message_id, remote) in remote_tags {
+		let stored = get_tags(&db, &message_id)?;
+		let mut local = stored.iter().cloned().collect::<BTreeSet<_>>();
+		if let Some((added, removed)) = keyword_edits.get(&message_id) {
+			local.extend(added.iter().cloned());
+			local.retain(|x| !removed.contains(x));
+		}
+		let base = get_synced_tags(&db, &message_id)?;
+		let merged = merge_tags(&local.into_iter().collect_vec(), &remote.into_iter().collect_vec(), &base);
+		let outdated = stored != merged || base != merged;
+		merged_tags.insert(message_id, (merged, outdated));
+	}
+
 	// start a transaction to fully simulate fetching behaviour (drop changes afterwards)
 	let tx = db.transaction()?;
-	let mut have_mail = tx.prepare("SELECT mailbox, uid, flags FROM mail WHERE message_id = ?")?;
-	let mut delete_mail = tx.prepare("DELETE FROM mail WHERE mailbox = ? AND uid = ?")?;
-	let mut all_mail = tx.prepare("SELECT uid, message_id, flags FROM mail WHERE mailbox = ?")?;
-	let mut save_mail = tx.prepare("INSERT INTO mail VALUES (?,?,?,?)")?;
-	let mut maildirs: HashMap<String, Maildir> = names.iter().map(|&x| (x.name().to_owned(), get_maildir(x.name()).unwrap())).collect();
+	let mut have_mail = tx.prepare(HAVE_MAIL)?;
+	let mut delete_mail = tx.prepare(DELETE_MAIL)?;
+	let mut all_mail = tx.prepare("SELECT uid_validity, uid, message_id, flags FROM mail WHERE mailbox = ?")?;
+	let mut save_mail = tx.prepare(SAVE_MAIL)?;
 	let mut printed_trash_warning = false;
 	let trash_dir = names.iter().filter(|x| x.attributes().iter().any(|x| *x == TRASH)).map(|x| x.name()).next();
 	let mut to_remove: HashMap<String, _> = HashMap::new();
 	for &name in &names {
-		let mailbox = name.name();
+		let mailbox = &*local[name.name()];
 		// if the user specified some mailboxes, only process those
-		if !mailboxes.is_empty() && !mailboxes.iter().any(|x| x == mailbox) {
+		if !mailboxes.is_empty() && !mailboxes.iter().any(|x| x == mailbox || x == name.name()) {
 			continue;
 		}
 		let is_trash = name.attributes().iter().any(|x| *x == TRASH);
 		let remote_mails = remote.get_mut(mailbox).unwrap();
 		println!("selecting {}", mailbox);
-		imap_session.select(mailbox).context("select failed")?;
-		let all_mails = all_mail.query_map(params![mailbox], map3rows::<i64, String, String>)?;
+		imap_session.select(name.name())?;
+		let all_mails = all_mail.query_map(params![mailbox], |row|
+			Ok((MaildirID::from_row(row, 0)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?)))?;
+		// mails trashed or deleted in this run, not to be fetched again
+		let mut removed_now = HashSet::new();
 		for x in all_mails {
 			let (uid, mid, flags) = x?;
-			let uid: MaildirID = uid.into();
 			if flags.contains(TRASHED) && !is_trash {
 				if let Some(_) = trash_dir {
 					println!("trashing: {}/{}", mailbox, uid);
@@ -140,7 +374,8 @@ pub fn compute_sync_actions(
 					} else {
 						actions.push(TrashLocal(mailbox.to_owned(), uid));
 					}
-					delete_mail.execute(params![mailbox, uid])?;
+					delete_mail.execute(params![mailbox, uid.uid_validity, uid.uid])?;
+					removed_now.insert(mid);
 				} else if !printed_trash_warning {
 					println!("Warning: unable to trash mail, no trash folder found!");
 					printed_trash_warning = true;
@@ -152,17 +387,35 @@ pub fn compute_sync_actions(
 				} else {
 					actions.push(DeleteLocal(mailbox.to_owned(), uid));
 				}
-				delete_mail.execute(params![mailbox, uid])?;
+				delete_mail.execute(params![mailbox, uid.uid_validity, uid.uid])?;
+				removed_now.insert(mid);
 			}
 		}
 
 		let mut to_flag = Vec::new();
+		let mut to_tag = Vec::new();
 		let mut to_fetch = Vec::new();
 		let mut to_hardlink = Vec::new();
 		for (message_id, entry) in remote_mails.iter_mut() {
+			if removed_now.contains(message_id) {
+				continue;
+			}
 			let (uid1, uid2, full_uid, remote_flags) = entry;
-			let local = have_mail.query_map(params![message_id], map3rows::<String, MaildirID, String>)?.map(|x| x.unwrap()).collect_vec();
-			
+			let (tags, outdated) = &merged_tags[message_id];
+			let remote_keywords = keywords(remote_flags);
+			let add = tags.iter().filter(|x| !remote_keywords.contains(x)).cloned().collect_vec();
+			let remove = remote_keywords.iter().filter(|x| !tags.contains(x)).cloned().collect_vec();
+			if *outdated || !add.is_empty() || !remove.is_empty() {
+				to_tag.push(TagUpdate {
+					id: *full_uid,
+					message_id: message_id.clone(),
+					add,
+					remove,
+					tags: tags.clone(),
+				});
+			}
+			let local = have_mail.query_map(params![message_id], local_mail)?.collect::<rusqlite::Result<Vec<_>>>()?;
+
 			if let Some((_, full_uid, flags)) = local.iter().filter(|x| x.0 == mailbox && x.1 == *full_uid).next() {
 				to_flag.push((*full_uid, remote_flags.clone(), flags.clone()));
 				continue;
@@ -171,16 +424,24 @@ pub fn compute_sync_actions(
 				let (_, _, flags) = &local[0];
 				let new_uid = MaildirID::new(*uid1, *uid2);
 				to_hardlink.push((new_uid, message_id.clone(), remote_flags.clone()));
-				save_mail.execute(params![mailbox, new_uid.to_i64(), message_id, flags])?;
+				save_mail.execute(params![mailbox, new_uid.uid_validity, new_uid.uid, message_id, flags])?;
 			} else if !is_trash { // do not fetch trashed mail
 				println!("fetching {:?} {:?} as it is not in {:?}", uid2, message_id, local);
 				let new_uid = MaildirID::new(*uid1, *uid2);
 				to_fetch.push(new_uid);
 			}
 		}
+		// keep the actions independent of the hash map order
+		to_flag.sort_unstable_by_key(|x| x.0);
+		to_tag.sort_unstable_by_key(|x| x.id);
+		to_hardlink.sort_unstable_by_key(|x| x.0);
+		to_fetch.sort_unstable();
 		if !to_flag.is_empty() {
 			actions.push(UpdateFlags(mailbox.to_string(), to_flag));
 		}
+		if !to_tag.is_empty() {
+			actions.push(UpdateTags(mailbox.to_string(), to_tag));
+		}
 		if !to_hardlink.is_empty() {
 			actions.push(Hardlink(mailbox.to_string(), to_hardlink));
 		}
@@ -189,14 +450,12 @@ pub fn compute_sync_actions(
 		}
 
 		let mails = all_mail.query_map(params![mailbox], |row|
-			Ok((load_i64(row.get::<_, i64>(0)?), row.get::<_, String>(1)?)))?
-			.map(|x| x.unwrap()).collect_vec();
+			Ok((MaildirID::from_row(row, 0)?, row.get::<_, String>(2)?)))?
+			.collect::<rusqlite::Result<Vec<_>>>()?;
 		let mut removed = Vec::new();
-		for (uid, message_id) in mails {
-			let uid1 = (uid >> 32) as u32;
-			let uid2 = ((uid << 32) >> 32) as u32;
+		for (id, message_id) in mails {
 			if !remote_mails.contains_key(&message_id) && !message_id.ends_with("@no-message-id>") {
-				removed.push((uid1, uid2, uid));
+				removed.push(id);
 			}
 		}
 		if !removed.is_empty() {
@@ -211,6 +470,272 @@ pub fn compute_sync_actions(
 	Ok((actions, remote))
 }
 
+pub fn perform_sync_actions(
+	account: &Account,
+	actions: Vec<SyncAction>,
+	remote: RemoteMails
+) -> Result<()> {
+	let db = account.get_db()?;
+	let mut imap_session = ReconnectingSession::connect(account)?;
+	println!("getting capabilities..");
+	let caps = imap_session.retry(|s| s.capabilities())?;
+	println!("capabilities: {}", caps.iter().map(|x| format!("{:?}", x)).join(" "));
+
+	let mut names = Vec::new();
+	let list = imap_session.retry(|s| s.list(None, Some("*")))?;
+	for x in list.iter() {
+		println!("{:?}", x);
+		names.push(x);
+	}
+	let trash_dir = names.iter().filter(|x| x.attributes().iter().any(|x| *x == TRASH)).map(|x| x.name()).next();
+	if trash_dir.is_none() {
+		println!("Warning: unable to trash mail, no trash folder found!");
+	}
+	// server names by local name
+	let server_names = local_names(&names).into_iter().map(|(server, local)| (local, server)).collect::<HashMap<_, _>>();
+
+	let mut have_mail = db.prepare(HAVE_MAIL)?;
+	let mut delete_mail = db.prepare(DELETE_MAIL)?;
+	let mut save_mail = db.prepare(SAVE_MAIL)?;
+	let mut synced_flags = db.prepare("SELECT synced_flags FROM mail WHERE mailbox = ? AND uid_validity = ? AND uid = ?")?;
+	// merged flags of the mails, as now stored on the server
+	let mut merged_flags = HashMap::new();
+	let mut maildirs: HashMap<String, Maildir> = server_names.keys().map(|x| Ok((x.clone(), account.get_maildir(x)?))).collect::<Result<_>>()?;
+	macro_rules! ensure_mailbox {
+		($name:expr) => {{
+			if !maildirs.contains_key($name) {
+				maildirs.insert($name.to_owned(), account.get_maildir($name)?);
+			}
+			&maildirs[$name]
+		}}
+	}
+	let mut selection = None;
+	let mut uid_valid = None;
+	// whether the selected mailbox accepts new keywords
+	let mut may_create = false;
+
+	for action in actions {
+		if let Some(mailbox) = action.mailbox() {
+			if selection.is_none() || selection.as_ref().unwrap() != mailbox {
+				if selection.is_some() {
+					println!("expunging..");
+					imap_session.expunge().map_err(|e| InboxidError::imap(selection.as_ref().unwrap(), e))?;
+				}
+				println!("selecting {}", mailbox);
+				let resp = imap_session.select(server_names.get(mailbox).map(|x| &**x).unwrap_or(mailbox))?;
+				uid_valid = resp.uid_validity;
+				// without PERMANENTFLAGS all flags may be changed permanently
+				may_create = resp.permanent_flags.is_empty() || resp.permanent_flags.contains(&Flag::MayCreate);
+				selection = Some(mailbox.to_string());
+			}
+		}
+		macro_rules! check_valid {
+			($mailbox:expr, $uid_validity:expr) => {
+				if uid_valid.is_none() || $uid_validity != uid_valid.unwrap() {
+					let e = InboxidError::UidValidityChanged {
+						mailbox: $mailbox.to_string(),
+						expected: $uid_validity,
+						actual: uid_valid
+					};
+					println!("Warning: {}, unable to process action!", e);
+					continue;
+				}
+			}
+		}
+		macro_rules! update_flags {
+			($mailbox:expr, $id:expr, $remote_flags:expr, $flags:expr) => {
+				// flags of the last sync, to see which side changed a flag
+				let base = synced_flags.query_row(params![$mailbox, $id.uid_validity, $id.uid], |row| row.get::<_, Option<String>>(0)).optional()?.flatten();
+				let merged = merge_flags($flags, $remote_flags, base.as_deref(), account.conflict_winner);
+				let wanted = maildir_flags_to_imap(&merged);
+				let add = wanted.iter().filter(|x| !$remote_flags.contains(*x)).cloned().collect_vec();
+				let remove = $remote_flags.iter()
+					.filter(|x| imap_flag_to_maildir(x).map(|x| SYNCED_FLAGS.contains(&x)).unwrap_or(false) && !wanted.contains(*x))
+					.cloned()
+					.collect_vec();
+				if !add.is_empty() {
+					println!("setting flags {} on {}/{}", imap_flags_to_cmd(&add), $mailbox, $id.uid);
+					imap_session.retry(|s| s.uid_store($id.to_imap(), format!("+FLAGS.SILENT {}", imap_flags_to_cmd(&add))))?;
+					$remote_flags.extend(add);
+				}
+				if !remove.is_empty() {
+					println!("removing flags {} on {}/{}", imap_flags_to_cmd(&remove), $mailbox, $id.uid);
+					imap_session.retry(|s| s.uid_store($id.to_imap(), format!("-FLAGS.SILENT {}", imap_flags_to_cmd(&remove))))?;
+					$remote_flags.retain(|x| !remove.contains(x));
+				}
+				merged_flags.insert(($mailbox.to_string(), *$id), merged);
+			}
+		}
+		match action {
+    		TrashRemote(mailbox, id) => {
+				check_valid!(mailbox, id.uid_validity);
+				if let Some(trash_dir) = trash_dir {
+					println!("trashing: {}/{}", mailbox, id.uid);
+					imap_session.uid_mv(id.to_imap(), trash_dir).map_err(|e| InboxidError::imap(&mailbox, e))?;
+					let gone = ensure_mailbox!(GONE);
+					let uid_name = id.to_string();
+					let _ = maildir_cp(&maildirs[&mailbox], gone, &uid_name, &uid_name, "", true);
+					maildirs[&mailbox].delete(&uid_name).map_err(|e| InboxidError::maildir(&mailbox, id, e))?;
+					delete_mail.execute(params![mailbox, id.uid_validity, id.uid])?;
+					unindex_mail(&db, &mailbox, id)?;
+				}
+			},
+    		TrashLocal(mailbox, id) => {
+				check_valid!(mailbox, id.uid_validity);
+				println!("trashing: {}/{}", mailbox, id.uid);
+				let gone = ensure_mailbox!(GONE);
+				let uid_name = id.to_string();
+				let _ = maildir_cp(&maildirs[&mailbox], gone, &uid_name, &uid_name, "", true);
+				maildirs[&mailbox].delete(&uid_name).map_err(|e| InboxidError::maildir(&mailbox, id, e))?;
+				delete_mail.execute(params![mailbox, id.uid_validity, id.uid])?;
+				unindex_mail(&db, &mailbox, id)?;
+			},
+    		DeleteRemote(mailbox, id) => {
+				imap_session.retry(|s| s.uid_store(id.to_imap(), "+FLAGS.SILENT (\\Deleted)"))?;
+				delete_mail.execute(params![mailbox, id.uid_validity, id.uid])?;
+				unindex_mail(&db, &mailbox, id)?;
+				maildirs[&mailbox].delete(&id.to_string()).map_err(|e| InboxidError::maildir(&mailbox, id, e))?;
+			},
+    		DeleteLocal(mailbox, id) => {
+				delete_mail.execute(params![mailbox, id.uid_validity, id.uid])?;
+				unindex_mail(&db, &mailbox, id)?;
+				maildirs[&mailbox].delete(&id.to_string()).map_err(|e| InboxidError::maildir(&mailbox, id, e))?;
+			},
+			UpdateFlags(mailbox, mut ids) => {
+				for (id, remote_flags, flags) in &mut ids {
+					check_valid!(mailbox, id.uid_validity);
+					// other maildir clients only change the file
+					let flags = maildirs[&mailbox].find(&id.to_string()).map(|x| x.flags().to_owned()).unwrap_or_else(|| flags.clone());
+					update_flags!(mailbox, id, remote_flags, &flags);
+				}
+			},
+			UpdateTags(mailbox, updates) => {
+				let mut warned = false;
+				for update in updates {
+					check_valid!(mailbox, update.id.uid_validity);
+					// new keywords can only be stored if the mailbox allows them
+					let (add, withheld) = if may_create { (&update.add[..], &[][..]) } else { (&[][..], &update.add[..]) };
+					if !withheld.is_empty() && !warned {
+						println!("Warning: {} does not allow keywords, unable to store new tags", mailbox);
+						warned = true;
+					}
+					if !add.is_empty() || !update.remove.is_empty() {
+						println!("updating tags of {}/{}", mailbox, update.id.uid);
+					}
+					if !add.is_empty() {
+						imap_session.retry(|s| s.uid_store(update.id.to_imap(), format!("+FLAGS.SILENT ({})", add.join(" "))))?;
+					}
+					if !update.remove.is_empty() {
+						imap_session.retry(|s| s.uid_store(update.id.to_imap(), format!("-FLAGS.SILENT ({})", update.remove.join(" "))))?;
+					}
+					set_tags(&db, &update.message_id, &update.tags)?;
+					// only remember the tags the server has as synced
+					let synced = update.tags.iter().filter(|x| !withheld.contains(x)).cloned().collect_vec();
+					set_synced_tags(&db, &update.message_id, &synced)?;
+				}
+			},
+    		Hardlink(mailbox, mut ids) => {
+				for (new_uid, message_id, remote_flags) in &mut ids {
+					check_valid!(mailbox, new_uid.uid_validity);
+					let local = have_mail.query_map(params![&*message_id], local_mail)?.collect::<rusqlite::Result<Vec<_>>>()?;
+					let (inbox, full_uid, flags) = &local[0];
+					let local_id = full_uid.to_string();
+					let new_id = new_uid.to_string();
+					// hardlink mail
+					let maildir1 = ensure_mailbox!(inbox.as_str());
+					let maildir2 = &maildirs[&mailbox];
+					println!("hardlinking: {}/{} -> {}/{}", inbox, local_id, mailbox, new_id);
+					maildir_cp(maildir1, maildir2, &local_id, &new_id, flags, false)?;
+					save_mail.execute(params![mailbox, new_uid.uid_validity, new_uid.uid, &*message_id, flags])?;
+					copy_summary(&db, inbox, *full_uid, &mailbox, *new_uid)?;
+					copy_index(&db, inbox, *full_uid, &mailbox, *new_uid)?;
+					update_flags!(mailbox, new_uid, remote_flags, flags);
+				}
+			},
+    		Fetch(mailbox, to_fetch) => {
+				let maildir = ensure_mailbox!(&mailbox);
+				check_valid!(mailbox, to_fetch[0].uid_validity);
+
+				let fetch_range = to_fetch.into_iter().map(|x| x.uid.to_string()).join(",");
+				let fetch = imap_session.retry(|s| s.uid_fetch(&fetch_range, "RFC822"))?;
+				
+				for mail in fetch.iter() {
+					println!("fetching: {}/{}", mailbox, mail.uid.unwrap());
+					let id = MaildirID::new(uid_valid.unwrap(), mail.uid.unwrap());
+					let id_name = id.to_string();
+					if !maildir.exists(&id_name) {
+						let mail_data = mail.body().unwrap_or_default();
+						let flags = imap_flags_to_maildir("".into(), mail.flags());
+						maildir.store_cur_with_id_flags(&id_name, &flags, mail_data).map_err(|e| InboxidError::maildir(&mailbox, id, e))?;
+					
+						let parsed = parse_mail(&mail_data)?;
+						let message_id = parsed.get_headers().message_id(&mailbox, id);
+						save_mail.execute(params![mailbox, id.uid_validity, id.uid, message_id, flags])?;
+						let summary = MailSummary::new(&mailbox, id, &flags, &parsed, mail_data.len() as u64, maildir.find_filename(&id_name).as_deref());
+						save_summary(&db, &mailbox, &summary)?;
+						harvest_addresses(&db, &summary, &parsed)?;
+						index_mail(&db, &mailbox, id, &parsed)?;
+					} else {
+						println!("warning: DB outdated, downloaded mail again");
+					}
+				}
+			},
+    		RemoveStale(to_remove) => {
+				for mailbox in to_remove.keys() {
+					for &id in &to_remove[&*mailbox] {
+						let uid_name = id.to_string();
+						println!("removing: {}/{}", mailbox, uid_name);
+						let gone = ensure_mailbox!(GONE);
+						let maildir = &maildirs[&*mailbox];
+						// hardlink should only fail if the mail was already deleted
+						let _ = maildir_cp(maildir, gone, &uid_name, &uid_name, "", true);
+						maildir.delete(&uid_name).map_err(|e| InboxidError::maildir(mailbox, id, e))?;
+						delete_mail.execute(params![mailbox, id.uid_validity, id.uid])?;
+						unindex_mail(&db, mailbox, id)?;
+					}
+				}
+			},
+		}
+	}
+	if let Some(mailbox) = selection {
+		println!("expunging..");
+		imap_session.expunge().map_err(|e| InboxidError::imap(&mailbox, e))?;
+	}
+	// final flag update, the tags are stored as keyword letters
+	let mut update_flags = db.prepare("UPDATE mail SET flags = ?1, synced_flags = ?5 WHERE mailbox = ?2 AND uid_validity = ?3 AND uid = ?4 AND (flags != ?1 OR synced_flags IS NOT ?5)")?;
+	for (mailbox, remote_mails) in remote {
+		let maildir = ensure_mailbox!(&mailbox);
+		let mut table = KeywordTable::load(maildir)?;
+		for (message_id, (_, _, id, flags)) in &remote_mails {
+			let entry = match maildir.find(&id.to_string()) {
+				Some(x) => x,
+				None => continue
+			};
+			let letters = table.letters(&get_tags(&db, message_id)?);
+			// mails without flag update (like fetched mails) have the flags of the server
+			let synced = merged_flags.remove(&(mailbox.clone(), *id)).unwrap_or_else(|| imap_flags_to_maildir(String::new(), flags));
+			let f = entry.flags().replace(|c: char| c == UNREAD || c.is_ascii_lowercase() || SYNCED_FLAGS.contains(&c), "");
+			let f = Maildir::normalize_flags(&(f + &synced + &letters));
+			if f != entry.flags() {
+				maildir.set_flags(&id.to_string(), &f).map_err(|e| InboxidError::maildir(&mailbox, *id, e))?;
+			}
+			// the mail table has the flags of the file, to find changes by other clients
+			update_flags.execute(params![f, mailbox, id.uid_validity, id.uid, synced])?;
+		}
+		table.save(maildir)?;
+	}
+	Ok(())
+}
+
+const HAVE_MAIL: &str = "SELECT mailbox, uid_validity, uid, flags FROM mail WHERE message_id = ?";
+const DELETE_MAIL: &str = "DELETE FROM mail WHERE mailbox = ? AND uid_validity = ? AND uid = ?";
+const SAVE_MAIL: &str = "INSERT OR REPLACE INTO mail (mailbox, uid_validity, uid, message_id, flags) VALUES (?,?,?,?,?)";
+
+/// Map a (mailbox, uid_validity, uid, flags) row.
+fn local_mail(row: &Row) -> rusqlite::Result<(String, MaildirID, String)> {
+	Ok((row.get(0)?, MaildirID::from_row(row, 1)?, row.get(3)?))
+}
+
 pub fn map3rows<A: FromSql, B: FromSql, C: FromSql>(row: &Row) -> rusqlite::Result<(A, B, C)> {
 	let a = row.get::<_, A>(0)?;
 	let b = row.get::<_, B>(1)?;
@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+
+use imap::types::Flag;
+use rusqlite::params;
+
+use inboxid_lib::*;
+use inboxid_sync::*;
+use inboxid_sync::SyncAction::*;
+use inboxid_test_support::*;
+
+const INBOX: u32 = 100;
+const TRASH: u32 = 200;
+const ARCHIVE: u32 = 300;
+
+fn id(uid_validity: u32, uid: u32) -> MaildirID {
+	MaildirID::new(uid_validity, uid)
+}
+
+/// Server with two read mails in the INBOX, an empty Archive and Trash.
+/// The local state is already in sync with the server.
+fn setup(name: &str) -> (FakeServer, Account) {
+	setup_with_capabilities(name, &[])
+}
+
+fn setup_with_capabilities(name: &str, capabilities: &[&str]) -> (FakeServer, Account) {
+	let server = FakeServer::start_with_capabilities(vec![
+		Mailbox::new("INBOX", INBOX)
+			.with_message(&mail("a@example.com", "first"), &["\\Seen"])
+			.with_message(&mail("b@example.com", "second"), &["\\Seen"]),
+		Mailbox::new("Archive", ARCHIVE),
+		Mailbox::new("Trash", TRASH).with_attribute("\\Trash"),
+	], capabilities);
+	let account = server.account(&test_dir(name));
+	sync(&account);
+	(server, account)
+}
+
+fn sync(account: &Account) {
+	migrate_mailbox_names(account).unwrap();
+	let (actions, remote) = compute_sync_actions(account, &[]).unwrap();
+	perform_sync_actions(account, actions, remote).unwrap();
+}
+
+/// Change the flags of a mail like inboxid-browse does.
+fn set_flags(account: &Account, mailbox: &str, id: MaildirID, flags: &str) {
+	account.get_maildir(mailbox).unwrap().update_flags(&id.to_string(), |_| flags.to_owned()).unwrap();
+	account.get_db().unwrap().execute("UPDATE mail SET flags = ? WHERE mailbox = ? AND uid_validity = ? AND uid = ?", params![flags, mailbox, id.uid_validity, id.uid]).unwrap();
+}
+
+fn db_rows(account: &Account) -> Vec<(String, MaildirID, String, String)> {
+	let db = account.get_db().unwrap();
+	let mut stmt = db.prepare("SELECT mailbox, uid_validity, uid, message_id, flags FROM mail ORDER BY mailbox, uid_validity, uid").unwrap();
+	let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, MaildirID::from_row(row, 1)?, row.get(3)?, row.get(4)?))).unwrap();
+	rows.collect::<rusqlite::Result<_>>().unwrap()
+}
+
+fn has_local(account: &Account, mailbox: &str, id: MaildirID) -> bool {
+	account.get_maildir(mailbox).unwrap().find_filename(&id.to_string()).is_some()
+}
+
+fn seen(id: MaildirID) -> (MaildirID, Vec<Flag<'static>>, String) {
+	(id, vec![Flag::Seen], "S".to_owned())
+}
+
+#[test]
+fn initial_fetch() {
+	let server = FakeServer::start(vec![
+		Mailbox::new("INBOX", INBOX)
+			.with_message(&mail("a@example.com", "first"), &["\\Seen"])
+			.with_message(&mail("b@example.com", "second"), &["\\Seen"]),
+		Mailbox::new("Trash", TRASH).with_attribute("\\Trash"),
+	]);
+	let account = server.account(&test_dir("initial_fetch"));
+
+	let (actions, remote) = compute_sync_actions(&account, &[]).unwrap();
+	assert_eq!(actions, vec![
+		Fetch("INBOX".to_owned(), vec![id(INBOX, 1), id(INBOX, 2)]),
+		RemoveStale(HashMap::new()),
+	]);
+	perform_sync_actions(&account, actions, remote).unwrap();
+
+	assert!(has_local(&account, "INBOX", id(INBOX, 1)));
+	assert!(has_local(&account, "INBOX", id(INBOX, 2)));
+	assert_eq!(db_rows(&account), vec![
+		("INBOX".to_owned(), id(INBOX, 1), "<a@example.com>".to_owned(), "S".to_owned()),
+		("INBOX".to_owned(), id(INBOX, 2), "<b@example.com>".to_owned(), "S".to_owned()),
+	]);
+	assert!(server.commands().iter().any(|x| x.starts_with("UID FETCH 1,2 RFC822")));
+}
+
+#[test]
+fn trash() {
+	let (server, account) = setup("trash");
+	set_flags(&account, "INBOX", id(INBOX, 1), "ST");
+
+	let (actions, remote) = compute_sync_actions(&account, &[]).unwrap();
+	assert_eq!(actions, vec![
+		TrashRemote("INBOX".to_owned(), id(INBOX, 1)),
+		UpdateFlags("INBOX".to_owned(), vec![seen(id(INBOX, 2))]),
+		RemoveStale(HashMap::new()),
+	]);
+	perform_sync_actions(&account, actions, remote).unwrap();
+
+	assert_eq!(server.mailbox("INBOX").uids(), vec![2]);
+	assert_eq!(server.mailbox("Trash").uids(), vec![1]);
+	assert!(!has_local(&account, "INBOX", id(INBOX, 1)));
+	assert!(has_local(&account, ".gone", id(INBOX, 1)));
+	assert_eq!(db_rows(&account), vec![
+		("INBOX".to_owned(), id(INBOX, 2), "<b@example.com>".to_owned(), "S".to_owned()),
+	]);
+}
+
+#[test]
+fn delete() {
+	let (server, account) = setup("delete");
+	set_flags(&account, "INBOX", id(INBOX, 1), "SE");
+
+	let (actions, remote) = compute_sync_actions(&account, &[]).unwrap();
+	assert_eq!(actions, vec![
+		DeleteRemote("INBOX".to_owned(), id(INBOX, 1)),
+		UpdateFlags("INBOX".to_owned(), vec![seen(id(INBOX, 2))]),
+		RemoveStale(HashMap::new()),
+	]);
+	perform_sync_actions(&account, actions, remote).unwrap();
+
+	assert_eq!(server.mailbox("INBOX").uids(), vec![2]);
+	assert!(server.mailbox("Trash").messages.is_empty());
+	assert!(!has_local(&account, "INBOX", id(INBOX, 1)));
+	assert_eq!(db_rows(&account), vec![
+		("INBOX".to_owned(), id(INBOX, 2), "<b@example.com>".to_owned(), "S".to_owned()),
+	]);
+}
+
+#[test]
+fn hardlink() {
+	let (server, account) = setup("hardlink");
+	// another client copied the first mail into the archive
+	server.with_mailbox("Archive", |x| x.add(&mail("a@example.com", "first"), &["\\Seen"]));
+
+	let (actions, remote) = compute_sync_actions(&account, &[]).unwrap();
+	assert_eq!(actions, vec![
+		UpdateFlags("INBOX".to_owned(), vec![seen(id(INBOX, 1)), seen(id(INBOX, 2))]),
+		Hardlink("Archive".to_owned(), vec![(id(ARCHIVE, 1), "<a@example.com>".to_owned(), vec![Flag::Seen])]),
+		RemoveStale(HashMap::new()),
+	]);
+	perform_sync_actions(&account, actions, remote).unwrap();
+
+	assert!(has_local(&account, "Archive", id(ARCHIVE, 1)));
+	assert_eq!(db_rows(&account), vec![
+		("Archive".to_owned(), id(ARCHIVE, 1), "<a@example.com>".to_owned(), "S".to_owned()),
+		("INBOX".to_owned(), id(INBOX, 1), "<a@example.com>".to_owned(), "S".to_owned()),
+		("INBOX".to_owned(), id(INBOX, 2), "<b@example.com>".to_owned(), "S".to_owned()),
+	]);
+}
+
+#[test]
+fn remove_stale() {
+	let (server, account) = setup("remove_stale");
+	// another client deleted the second mail
+	server.with_mailbox("INBOX", |x| x.remove(2));
+
+	let (actions, remote) = compute_sync_actions(&account, &[]).unwrap();
+	let mut stale = HashMap::new();
+	stale.insert("INBOX".to_owned(), vec![id(INBOX, 2)]);
+	assert_eq!(actions, vec![
+		UpdateFlags("INBOX".to_owned(), vec![seen(id(INBOX, 1))]),
+		RemoveStale(stale),
+	]);
+	perform_sync_actions(&account, actions, remote).unwrap();
+
+	assert!(!has_local(&account, "INBOX", id(INBOX, 2)));
+	assert!(has_local(&account, ".gone", id(INBOX, 2)));
+	assert_eq!(db_rows(&account), vec![
+		("INBOX".to_owned(), id(INBOX, 1), "<a@example.com>".to_owned(), "S".to_owned()),
+	]);
+}
+
+#[test]
+fn seen_flag_is_pushed() {
+	let (server, account) = setup("seen_flag");
+	set_flags(&account, "INBOX", id(INBOX, 2), "U");
+
+	sync(&account);
+
+	assert!(server.mailbox("INBOX").message(1).unwrap().has_flag("\\Seen"));
+	assert!(!server.mailbox("INBOX").message(2).unwrap().has_flag("\\Seen"));
+}
+
+const FULL_SCAN: &str = "UID FETCH 1:* (FLAGS BODY[HEADER.FIELDS (MESSAGE-ID)])";
+
+#[test]
+fn condstore_fetches_changes_only() {
+	let (server, account) = setup_with_capabilities("condstore_changes", &["CONDSTORE"]);
+	let before = server.commands().len();
+	// another client marked the second mail as unread
+	server.with_mailbox("INBOX", |x| x.set_flags(2, &[]));
+
+	let (actions, _) = compute_sync_actions(&account, &[]).unwrap();
+	assert_eq!(actions, vec![
+		UpdateFlags("INBOX".to_owned(), vec![seen(id(INBOX, 1)), (id(INBOX, 2), vec![], "S".to_owned())]),
+		RemoveStale(HashMap::new()),
+	]);
+
+	let commands = &server.commands()[before..];
+	assert!(!commands.iter().any(|x| x == FULL_SCAN));
+	assert_eq!(commands.iter().filter(|x| x.contains("CHANGEDSINCE")).count(), 1);
+	assert!(!commands.iter().any(|x| x.starts_with("UID SEARCH")));
+}
+
+#[test]
+fn condstore_detects_expunges() {
+	let (server, account) = setup_with_capabilities("condstore_expunge", &["CONDSTORE"]);
+	let before = server.commands().len();
+	// another client deleted the second mail
+	server.with_mailbox("INBOX", |x| x.remove(2));
+
+	let (actions, _) = compute_sync_actions(&account, &[]).unwrap();
+	let mut stale = HashMap::new();
+	stale.insert("INBOX".to_owned(), vec![id(INBOX, 2)]);
+	assert_eq!(actions, vec![
+		UpdateFlags("INBOX".to_owned(), vec![seen(id(INBOX, 1))]),
+		RemoveStale(stale),
+	]);
+
+	let commands = &server.commands()[before..];
+	assert!(!commands.iter().any(|x| x == FULL_SCAN));
+	assert!(commands.iter().any(|x| x == "UID SEARCH ALL"));
+}
+
+#[test]
+fn qresync_reports_expunges() {
+	let (server, account) = setup_with_capabilities("qresync", &["CONDSTORE", "QRESYNC"]);
+	let before = server.commands().len();
+	server.with_mailbox("INBOX", |x| x.remove(2));
+
+	let (actions, _) = compute_sync_actions(&account, &[]).unwrap();
+	let mut stale = HashMap::new();
+	stale.insert("INBOX".to_owned(), vec![id(INBOX, 2)]);
+	assert_eq!(actions, vec![
+		UpdateFlags("INBOX".to_owned(), vec![seen(id(INBOX, 1))]),
+		RemoveStale(stale),
+	]);
+
+	let commands = &server.commands()[before..];
+	assert!(commands.iter().any(|x| x.starts_with("EXAMINE \"INBOX\" (QRESYNC (")));
+	assert!(!commands.iter().any(|x| x == FULL_SCAN || x.starts_with("UID SEARCH")));
+	// enabled once per connection, before any mailbox is examined
+	let position = |command: &str| commands.iter().position(|x| x.starts_with(command));
+	assert_eq!(commands.iter().filter(|x| x.starts_with("ENABLE")).count(), 1);
+	assert!(position("ENABLE") < position("EXAMINE"));
+}
+
+#[test]
+fn reconnect_after_connection_loss() {
+	let (server, account) = setup("reconnect");
+	server.with_mailbox("INBOX", |x| x.add(&mail("c@example.com", "third"), &["\\Seen"]));
+	server.drop_connection_on("UID FETCH 3 RFC822");
+
+	sync(&account);
+
+	assert!(has_local(&account, "INBOX", id(INBOX, 3)));
+	assert_eq!(server.commands().iter().filter(|x| x.starts_with("UID FETCH 3 RFC822")).count(), 2);
+}
+
+fn search_hits(account: &Account, query: &str) -> Vec<(String, MaildirID)> {
+	let db = account.get_db().unwrap();
+	search(&db, query, 10).unwrap().into_iter().map(|x| (x.mailbox, x.id)).collect()
+}
+
+#[test]
+fn search_index_follows_sync() {
+	let (server, account) = setup("search_index");
+	assert_eq!(search_hits(&account, "subject:second"), vec![("INBOX".to_owned(), id(INBOX, 2))]);
+
+	server.with_mailbox("Archive", |x| x.add(&mail("b@example.com", "second"), &["\\Seen"]));
+	server.with_mailbox("INBOX", |x| x.remove(2));
+	sync(&account);
+
+	assert_eq!(search_hits(&account, "subject:second"), vec![("Archive".to_owned(), id(ARCHIVE, 1))]);
+}
+
+#[test]
+fn summaries_are_cached() {
+	let (_server, account) = setup("summaries");
+	let db = account.get_db().unwrap();
+	let maildir = account.get_maildir("INBOX").unwrap();
+
+	let mut summaries = load_summaries(&db, "INBOX", &maildir).unwrap();
+	summaries.sort_by_key(|x| x.id);
+	assert_eq!(summaries[0], MailSummary {
+		id: id(INBOX, 1),
+		flags: "S".to_owned(),
+		message_id: "<a@example.com>".to_owned(),
+		subject: "first".to_owned(),
+		from: "Alice <alice@example.com>".to_owned(),
+		date: 1612170000,
+		date_source: DateSource::Header,
+		in_reply_to: String::new(),
+		references: String::new(),
+		size: mail("a@example.com", "first").len() as u64,
+		has_attachment: false,
+		new: false,
+	});
+
+	// listing does not parse the mail again
+	db.execute("UPDATE mail SET subject = 'from cache' WHERE uid = 2", params![]).unwrap();
+	let summaries = load_summaries(&db, "INBOX", &maildir).unwrap();
+	assert!(summaries.iter().any(|x| x.subject == "from cache"));
+
+	// mail in new is listed as well
+	maildir.store_new_with_id(&id(INBOX, 9).to_string(), mail("c@example.com", "delivered").as_bytes()).unwrap();
+	let summaries = load_summaries(&db, "INBOX", &maildir).unwrap();
+	let new = summaries.iter().filter(|x| x.new).collect::<Vec<_>>();
+	assert_eq!(new.len(), 1);
+	assert_eq!((new[0].id, &*new[0].subject), (id(INBOX, 9), "delivered"));
+	maildir.move_new_to_cur(&id(INBOX, 9).to_string()).unwrap();
+	assert!(load_summaries(&db, "INBOX", &maildir).unwrap().iter().all(|x| !x.new));
+}
+
+#[test]
+fn tags_are_synced_as_keywords() {
+	let (server, account) = setup("tags");
+	let db = account.get_db().unwrap();
+	add_tag(&db, "<a@example.com>", "todo").unwrap();
+	// another client tagged the second mail
+	server.with_mailbox("INBOX", |x| x.set_flags(2, &["\\Seen", "work"]));
+
+	sync(&account);
+
+	assert!(server.mailbox("INBOX").message(1).unwrap().has_flag("todo"));
+	assert_eq!(get_tags(&db, "<b@example.com>").unwrap(), vec!["work"]);
+
+	// removals are merged as well
+	remove_tag(&db, "<a@example.com>", "todo").unwrap();
+	server.with_mailbox("INBOX", |x| x.set_flags(2, &["\\Seen"]));
+	sync(&account);
+
+	assert!(!server.mailbox("INBOX").message(1).unwrap().has_flag("todo"));
+	assert!(get_tags(&db, "<b@example.com>").unwrap().is_empty());
+	let (actions, _) = compute_sync_actions(&account, &[]).unwrap();
+	assert!(!actions.iter().any(|x| matches!(x, UpdateTags(..))));
+}
+
+#[test]
+fn nested_mailboxes_are_stored_in_directories() {
+	let server = FakeServer::start(vec![
+		Mailbox::new("INBOX", INBOX)
+			.with_message(&mail("a@example.com", "first"), &["\\Seen"]),
+		Mailbox::new("INBOX.Lists", ARCHIVE)
+			.with_message(&mail("b@example.com", "second"), &["\\Seen"]),
+		Mailbox::new("Entw&APw-rfe", TRASH)
+			.with_message(&mail("c@example.com", "draft"), &[]),
+	]);
+	let account = server.account(&test_dir("nested"));
+	// the mailboxes are not nested with `/` as delimiter, INBOX.Lists is stored under its server name
+	sync(&account);
+	assert!(has_local(&account, "INBOX.Lists", id(ARCHIVE, 1)));
+
+	server.set_delimiter(".");
+	// a dry run does not move anything
+	compute_sync_actions(&account, &[]).unwrap();
+	assert!(has_local(&account, "INBOX.Lists", id(ARCHIVE, 1)));
+	migrate_mailbox_names(&account).unwrap();
+	let (actions, _) = compute_sync_actions(&account, &[]).unwrap();
+	assert!(!actions.iter().any(|x| matches!(x, Fetch(..) | Hardlink(..))));
+	assert!(has_local(&account, "INBOX/Lists", id(ARCHIVE, 1)));
+	assert!(has_local(&account, "Entwürfe", id(TRASH, 1)));
+	let mut maildirs = account.get_maildirs().unwrap();
+	maildirs.sort_unstable();
+	assert_eq!(maildirs, vec!["Entwürfe", "INBOX", "INBOX/Lists"]);
+
+	// new mail is fetched into the nested mailbox
+	server.with_mailbox("INBOX.Lists", |x| x.add(&mail("d@example.com", "third"), &[]));
+	sync(&account);
+	assert!(has_local(&account, "INBOX/Lists", id(ARCHIVE, 2)));
+	assert!(db_rows(&account).iter().all(|x| x.0 != "INBOX.Lists"));
+}
+
+fn file_flags(account: &Account, mailbox: &str, id: MaildirID) -> String {
+	let entry = account.get_maildir(mailbox).unwrap().find(&id.to_string()).unwrap();
+	let mut flags = entry.flags().chars().collect::<Vec<_>>();
+	flags.sort_unstable();
+	flags.into_iter().collect()
+}
+
+#[test]
+fn flags_and_keywords_are_synced() {
+	let (server, account) = setup("keywords");
+	let maildir = account.get_maildir("INBOX").unwrap();
+	server.with_mailbox("INBOX", |x| x.set_flags(2, &["\\Seen", "\\Flagged", "\\Draft", "$Forwarded", "work"]));
+
+	sync(&account);
+	assert_eq!(file_flags(&account, "INBOX", id(INBOX, 2)), "DFPSa");
+	assert_eq!(maildir.get_file(KEYWORDS_FILE).unwrap(), "0 work\n");
+
+	// flags changed in inboxid, the keyword added by another maildir client
+	set_flags(&account, "INBOX", id(INBOX, 1), "FPS");
+	maildir.update_flags(&id(INBOX, 1).to_string(), |f| format!("{}a", f)).unwrap();
+	sync(&account);
+	let message = server.mailbox("INBOX").message(1).unwrap().clone();
+	assert!(["\\Flagged", "$Forwarded", "work"].iter().all(|x| message.has_flag(x)));
+	assert_eq!(get_tags(&account.get_db().unwrap(), "<a@example.com>").unwrap(), vec!["work"]);
+
+	// flag removed by another maildir client
+	maildir.update_flags(&id(INBOX, 1).to_string(), |f| f.replace('F', "")).unwrap();
+	sync(&account);
+	assert!(!server.mailbox("INBOX").message(1).unwrap().has_flag("\\Flagged"));
+	assert_eq!(file_flags(&account, "INBOX", id(INBOX, 1)), "PSa");
+
+	// keyword removed by another maildir client
+	maildir.update_flags(&id(INBOX, 2).to_string(), |f| f.replace('a', "")).unwrap();
+	sync(&account);
+	assert!(!server.mailbox("INBOX").message(2).unwrap().has_flag("work"));
+	assert_eq!(file_flags(&account, "INBOX", id(INBOX, 2)), "DFPS");
+}
+
+#[test]
+fn flags_are_merged_with_the_last_sync() {
+	let (server, account) = setup("merge");
+	let synced = |account: &Account, uid: u32| account.get_db().unwrap().query_row(
+		"SELECT synced_flags FROM mail WHERE uid_validity = ? AND uid = ?", params![INBOX, uid], |row| row.get::<_, String>(0)).unwrap();
+	assert_eq!(synced(&account, 1), "S");
+
+	// removed on the server, not pushed again
+	server.with_mailbox("INBOX", |x| x.set_flags(1, &[]));
+	server.with_mailbox("INBOX", |x| x.set_flags(2, &["\\Seen", "\\Flagged"]));
+	sync(&account);
+	assert_eq!(file_flags(&account, "INBOX", id(INBOX, 1)), "");
+	assert!(!server.mailbox("INBOX").message(1).unwrap().has_flag("\\Seen"));
+	assert_eq!(synced(&account, 2), "SF");
+
+	// removed locally by another maildir client
+	account.get_maildir("INBOX").unwrap().update_flags(&id(INBOX, 2).to_string(), |f| f.replace('F', "")).unwrap();
+	sync(&account);
+	assert!(!server.mailbox("INBOX").message(2).unwrap().has_flag("\\Flagged"));
+
+	// marked as unread locally and read on the server, the local change wins by default
+	server.with_mailbox("INBOX", |x| x.set_flags(1, &["\\Seen"]));
+	set_flags(&account, "INBOX", id(INBOX, 1), "U");
+	// other changes of both sides are merged
+	server.with_mailbox("INBOX", |x| x.set_flags(2, &["\\Seen", "\\Flagged"]));
+	set_flags(&account, "INBOX", id(INBOX, 2), "RS");
+	sync(&account);
+	assert!(!server.mailbox("INBOX").message(1).unwrap().has_flag("\\Seen"));
+	assert_eq!(file_flags(&account, "INBOX", id(INBOX, 1)), "");
+	let message = server.mailbox("INBOX").message(2).unwrap().clone();
+	assert!(message.has_flag("\\Answered") && message.has_flag("\\Flagged"));
+	assert_eq!(file_flags(&account, "INBOX", id(INBOX, 2)), "FRS");
+
+	// unless the server is configured to win
+	let account = Account { conflict_winner: ConflictWinner::Remote, ..account };
+	server.with_mailbox("INBOX", |x| x.set_flags(1, &["\\Seen"]));
+	set_flags(&account, "INBOX", id(INBOX, 1), "U");
+	sync(&account);
+	assert!(server.mailbox("INBOX").message(1).unwrap().has_flag("\\Seen"));
+	assert_eq!(file_flags(&account, "INBOX", id(INBOX, 1)), "S");
+	assert_eq!(synced(&account, 1), "S");
+}
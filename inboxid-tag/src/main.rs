@@ -0,0 +1,50 @@
+use std::convert::TryFrom;
+
+use inboxid_lib::*;
+use rusqlite::{Connection, OptionalExtension, params};
+
+const USAGE: &str = "usage: inboxid-tag add|remove <mailbox> <id> <tag>... | list <mailbox> <id> | show <tag> | all";
+
+fn main() -> Result<()> {
+	let args = get_args()?;
+	let db = get_db()?;
+	let args = args.iter().map(|x| &**x).collect::<Vec<_>>();
+	match &args[1..] {
+		["add", mailbox, id, tags @ ..] | ["remove", mailbox, id, tags @ ..] if !tags.is_empty() => {
+			let message_id = message_id(&db, mailbox, id)?;
+			for tag in tags {
+				if args[1] == "add" {
+					add_tag(&db, &message_id, tag)?;
+				} else {
+					remove_tag(&db, &message_id, tag)?;
+				}
+			}
+		},
+		["list", mailbox, id] => {
+			for tag in get_tags(&db, &message_id(&db, mailbox, id)?)? {
+				println!("{}", tag);
+			}
+		},
+		["show", tag] => {
+			for (mailbox, id) in tagged_mails(&db, tag)? {
+				println!("{}/{}", mailbox, id);
+			}
+		},
+		["all"] => {
+			for tag in all_tags(&db)? {
+				println!("{}", tag);
+			}
+		},
+		_ => return Err(InboxidError::Config(USAGE.to_owned()))
+	}
+	Ok(())
+}
+
+/// Find the Message-ID of a stored mail.
+fn message_id(db: &Connection, mailbox: &str, id: &str) -> Result<String> {
+	let id = MaildirID::try_from(id)?;
+	db.query_row("SELECT message_id FROM mail WHERE mailbox = ? AND uid_validity = ? AND uid = ?",
+		params![mailbox, id.uid_validity, id.uid], |row| row.get(0))
+		.optional()?
+		.ok_or_else(|| InboxidError::Config(format!("no mail {}/{}", mailbox, id)))
+}
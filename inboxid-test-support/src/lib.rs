@@ -0,0 +1,574 @@
+//! Scriptable in-memory IMAP server and helpers for testing inboxid
+//! without a real mail server.
+
+use std::{env, fs, io::{self, BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, path::{Path, PathBuf}, process, sync::{Arc, atomic::{AtomicUsize, Ordering}}, thread};
+
+use inboxid_lib::{Account, Security};
+use parking_lot::Mutex;
+
+/// A mail stored on the fake server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+	pub uid: u32,
+	/// IMAP flags, e.g. `\Seen`
+	pub flags: Vec<String>,
+	pub data: Vec<u8>,
+	/// modification sequence of the last change (CONDSTORE)
+	pub modseq: u64,
+}
+
+impl Message {
+	pub fn has_flag(&self, flag: &str) -> bool {
+		self.flags.iter().any(|x| x == flag)
+	}
+
+	/// The Message-ID header line, as returned for `BODY[HEADER.FIELDS (MESSAGE-ID)]`.
+	fn message_id_header(&self) -> Vec<u8> {
+		let data = String::from_utf8_lossy(&self.data);
+		let mut header = String::new();
+		let mut in_header = false;
+		for line in data.split("\r\n") {
+			if line.is_empty() {
+				break;
+			}
+			if line.starts_with(' ') || line.starts_with('\t') {
+				if in_header {
+					header += line;
+					header += "\r\n";
+				}
+				continue;
+			}
+			in_header = line.to_ascii_lowercase().starts_with("message-id:");
+			if in_header {
+				header += line;
+				header += "\r\n";
+			}
+		}
+		header += "\r\n";
+		header.into_bytes()
+	}
+}
+
+/// A mailbox on the fake server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mailbox {
+	pub name: String,
+	/// LIST attributes, e.g. `\Trash`
+	pub attributes: Vec<String>,
+	pub uid_validity: u32,
+	pub uid_next: u32,
+	pub highest_modseq: u64,
+	pub messages: Vec<Message>,
+	/// UIDs of expunged mails with the modseq of the expunge (QRESYNC)
+	pub expunged: Vec<(u32, u64)>,
+}
+
+impl Mailbox {
+	pub fn new(name: &str, uid_validity: u32) -> Self {
+		Self {
+			name: name.to_owned(),
+			attributes: Vec::new(),
+			uid_validity,
+			uid_next: 1,
+			highest_modseq: 1,
+			messages: Vec::new(),
+			expunged: Vec::new(),
+		}
+	}
+
+	pub fn with_attribute(mut self, attribute: &str) -> Self {
+		self.attributes.push(attribute.to_owned());
+		self
+	}
+
+	pub fn with_message(mut self, data: &str, flags: &[&str]) -> Self {
+		self.add(data, flags);
+		self
+	}
+
+	/// Store a new mail, returning its UID.
+	pub fn add(&mut self, data: &str, flags: &[&str]) -> u32 {
+		let uid = self.uid_next;
+		self.uid_next += 1;
+		self.highest_modseq += 1;
+		self.messages.push(Message {
+			uid,
+			flags: flags.iter().map(|&x| x.to_owned()).collect(),
+			data: data.as_bytes().to_owned(),
+			modseq: self.highest_modseq,
+		});
+		uid
+	}
+
+	/// Remove a mail (as if another client expunged it).
+	pub fn remove(&mut self, uid: u32) -> Option<Message> {
+		let idx = self.messages.iter().position(|x| x.uid == uid)?;
+		self.highest_modseq += 1;
+		self.expunged.push((uid, self.highest_modseq));
+		Some(self.messages.remove(idx))
+	}
+
+	/// Replace the flags of a mail (as if another client changed them).
+	pub fn set_flags(&mut self, uid: u32, flags: &[&str]) {
+		self.highest_modseq += 1;
+		let modseq = self.highest_modseq;
+		let message = self.messages.iter_mut().find(|x| x.uid == uid).expect("no such mail");
+		message.flags = flags.iter().map(|&x| x.to_owned()).collect();
+		message.modseq = modseq;
+	}
+
+	pub fn message(&self, uid: u32) -> Option<&Message> {
+		self.messages.iter().find(|x| x.uid == uid)
+	}
+
+	pub fn uids(&self) -> Vec<u32> {
+		self.messages.iter().map(|x| x.uid).collect()
+	}
+}
+
+/// Generate a simple mail.
+pub fn mail(message_id: &str, subject: &str) -> String {
+	format!("Message-ID: <{}>\r\n\
+		From: Alice <alice@example.com>\r\n\
+		To: bob@example.com\r\n\
+		Subject: {}\r\n\
+		Date: Mon, 1 Feb 2021 10:00:00 +0100\r\n\
+		\r\n\
+		Hello Bob!\r\n", message_id, subject)
+}
+
+/// IMAP server listening on localhost, serving the configured mailboxes.
+/// Every connection runs in its own thread and modifies the shared state.
+pub struct FakeServer {
+	port: u16,
+	mailboxes: Arc<Mutex<Vec<Mailbox>>>,
+	commands: Arc<Mutex<Vec<String>>>,
+	drop_on: Arc<Mutex<Option<String>>>,
+	delimiter: Arc<Mutex<String>>,
+}
+
+impl FakeServer {
+	pub fn start(mailboxes: Vec<Mailbox>) -> Self {
+		Self::start_with_capabilities(mailboxes, &[])
+	}
+
+	/// Start a server advertising additional capabilities, e.g. `CONDSTORE`.
+	pub fn start_with_capabilities(mailboxes: Vec<Mailbox>, capabilities: &[&str]) -> Self {
+		let mut capabilities = capabilities.iter().map(|&x| x.to_owned()).collect::<Vec<_>>();
+		capabilities.splice(0..0, vec!["IMAP4rev1".to_owned(), "MOVE".to_owned()]);
+		let capabilities = Arc::new(capabilities);
+		let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind fake IMAP server");
+		let port = listener.local_addr().unwrap().port();
+		let mailboxes = Arc::new(Mutex::new(mailboxes));
+		let commands = Arc::new(Mutex::new(Vec::new()));
+		let drop_on = Arc::new(Mutex::new(None));
+		let delimiter = Arc::new(Mutex::new("/".to_owned()));
+		let server = Self {
+			port,
+			mailboxes: Arc::clone(&mailboxes),
+			commands: Arc::clone(&commands),
+			drop_on: Arc::clone(&drop_on),
+			delimiter: Arc::clone(&delimiter),
+		};
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let stream = match stream {
+					Ok(x) => x,
+					Err(_) => break
+				};
+				let connection = Connection {
+					reader: BufReader::new(stream.try_clone().unwrap()),
+					writer: stream,
+					mailboxes: Arc::clone(&mailboxes),
+					commands: Arc::clone(&commands),
+					capabilities: Arc::clone(&capabilities),
+					drop_on: Arc::clone(&drop_on),
+					delimiter: Arc::clone(&delimiter),
+					selected: None,
+					read_only: false,
+					qresync: false,
+				};
+				thread::spawn(move || connection.run());
+			}
+		});
+		server
+	}
+
+	pub fn port(&self) -> u16 {
+		self.port
+	}
+
+	/// An account using this server, storing its data in `dir`.
+	pub fn account(&self, dir: &Path) -> Account {
+		Account {
+			host: "127.0.0.1".to_owned(),
+			port: Some(self.port),
+			security: Security::Plain,
+			user: "bob@example.com".to_owned(),
+			password_command: Some("echo hunter2".to_owned()),
+			maildir: dir.join("mail"),
+			db: dir.join("index.db"),
+			..Default::default()
+		}
+	}
+
+	/// Inspect or modify a mailbox of the server.
+	pub fn with_mailbox<T>(&self, name: &str, f: impl FnOnce(&mut Mailbox) -> T) -> T {
+		let mut mailboxes = self.mailboxes.lock();
+		let mailbox = mailboxes.iter_mut().find(|x| x.name == name).expect("no such mailbox");
+		f(mailbox)
+	}
+
+	pub fn mailbox(&self, name: &str) -> Mailbox {
+		self.with_mailbox(name, |x| x.clone())
+	}
+
+	/// All commands received so far (without tags).
+	pub fn commands(&self) -> Vec<String> {
+		self.commands.lock().clone()
+	}
+
+	/// Close the connection (once) instead of answering the next command starting with `prefix`.
+	pub fn drop_connection_on(&self, prefix: &str) {
+		*self.drop_on.lock() = Some(prefix.to_owned());
+	}
+
+	/// Use another hierarchy delimiter in LIST responses (`/` by default).
+	pub fn set_delimiter(&self, delimiter: &str) {
+		*self.delimiter.lock() = delimiter.to_owned();
+	}
+}
+
+struct Connection {
+	reader: BufReader<TcpStream>,
+	writer: TcpStream,
+	mailboxes: Arc<Mutex<Vec<Mailbox>>>,
+	commands: Arc<Mutex<Vec<String>>>,
+	capabilities: Arc<Vec<String>>,
+	drop_on: Arc<Mutex<Option<String>>>,
+	delimiter: Arc<Mutex<String>>,
+	selected: Option<String>,
+	read_only: bool,
+	/// whether QRESYNC was enabled
+	qresync: bool,
+}
+
+type Response = Result<Vec<u8>, String>;
+
+impl Connection {
+	fn run(mut self) -> io::Result<()> {
+		self.writer.write_all(b"* OK fake IMAP server ready\r\n")?;
+		let mut line = String::new();
+		loop {
+			line.clear();
+			if self.reader.read_line(&mut line)? == 0 {
+				return Ok(());
+			}
+			let input = line.trim_end();
+			let (tag, command) = split_word(input);
+			self.commands.lock().push(command.to_owned());
+			{
+				let mut drop_on = self.drop_on.lock();
+				if drop_on.as_ref().map(|x| command.starts_with(x.as_str())).unwrap_or(false) {
+					*drop_on = None;
+					return Ok(());
+				}
+			}
+			let (name, args) = split_word(command);
+			let name = name.to_ascii_uppercase();
+			let response = match &*name {
+				"CAPABILITY" => Ok(format!("* CAPABILITY {}\r\n", self.capabilities.join(" ")).into_bytes()),
+				"LOGIN" | "NOOP" => Ok(Vec::new()),
+				"ENABLE" => {
+					// RFC 5161: ENABLE is not allowed once a mailbox was selected
+					if self.selected.is_some() {
+						self.writer.write_all(format!("{} BAD ENABLE in selected state\r\n", tag).as_bytes())?;
+						continue;
+					}
+					Ok(self.enable(args))
+				},
+				"LOGOUT" => {
+					self.writer.write_all(format!("* BYE logging out\r\n{} OK LOGOUT completed\r\n", tag).as_bytes())?;
+					return Ok(());
+				},
+				"LIST" => Ok(self.list()),
+				"SELECT" => self.select(args, false),
+				"EXAMINE" => self.select(args, true),
+				"EXPUNGE" => self.expunge(),
+				"UID" => {
+					let (name, args) = split_word(args);
+					match &*name.to_ascii_uppercase() {
+						"FETCH" => self.fetch(args),
+						"STORE" => self.store(args),
+						"MOVE" => self.mv(args),
+						"SEARCH" => self.search(args),
+						_ => Err(format!("unsupported command UID {}", name))
+					}
+				},
+				_ => Err(format!("unsupported command {}", name))
+			};
+			match response {
+				Ok(data) => {
+					self.writer.write_all(&data)?;
+					self.writer.write_all(format!("{} OK {} completed\r\n", tag, name).as_bytes())?;
+				},
+				Err(e) => {
+					self.writer.write_all(format!("{} NO {}\r\n", tag, e).as_bytes())?;
+				}
+			}
+		}
+	}
+
+	fn enable(&mut self, args: &str) -> Vec<u8> {
+		let qresync = self.capabilities.iter().any(|x| x == "QRESYNC");
+		if qresync && args.split_whitespace().any(|x| x.eq_ignore_ascii_case("QRESYNC")) {
+			self.qresync = true;
+			return b"* ENABLED QRESYNC\r\n".to_vec();
+		}
+		b"* ENABLED\r\n".to_vec()
+	}
+
+	fn list(&self) -> Vec<u8> {
+		let mut out = String::new();
+		let delimiter = self.delimiter.lock().clone();
+		for mailbox in self.mailboxes.lock().iter() {
+			out += &format!("* LIST ({}) \"{}\" \"{}\"\r\n", mailbox.attributes.join(" "), delimiter, mailbox.name);
+		}
+		out.into_bytes()
+	}
+
+	fn select(&mut self, args: &str, read_only: bool) -> Response {
+		// the name may be followed by parameters like (CONDSTORE)
+		let name = match args.strip_prefix('"') {
+			Some(x) => unquote(&args[..x.find('"').map(|x| x + 2).unwrap_or(args.len())]),
+			None => split_word(args).0.to_owned()
+		};
+		let mailboxes = self.mailboxes.lock();
+		let mailbox = mailboxes.iter().find(|x| x.name == name).ok_or("no such mailbox")?;
+		let mut out = format!("* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
+			* {} EXISTS\r\n\
+			* 0 RECENT\r\n\
+			* OK [PERMANENTFLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft \\*)] Limited\r\n\
+			* OK [UIDVALIDITY {}] UIDs valid\r\n\
+			* OK [UIDNEXT {}] Predicted next UID\r\n",
+			mailbox.messages.len(), mailbox.uid_validity, mailbox.uid_next);
+		if self.condstore() {
+			out += &format!("* OK [HIGHESTMODSEQ {}] Highest\r\n", mailbox.highest_modseq);
+		}
+		// (QRESYNC (uidvalidity modseq)), only accepted after ENABLE QRESYNC
+		let upper = args.to_ascii_uppercase();
+		if let Some(start) = upper.find("(QRESYNC (") {
+			if !self.qresync {
+				return Err("QRESYNC is not enabled".to_owned());
+			}
+			let mut known = args[start + 10..].split(|c: char| c == ' ' || c == ')').map(|x| x.parse::<u64>().ok());
+			if let (Some(Some(uid_validity)), Some(Some(modseq))) = (known.next(), known.next()) {
+				let vanished = mailbox.expunged.iter().filter(|x| x.1 > modseq).map(|x| x.0.to_string()).collect::<Vec<_>>();
+				if uid_validity == mailbox.uid_validity as u64 && !vanished.is_empty() {
+					out += &format!("* VANISHED (EARLIER) {}\r\n", vanished.join(","));
+				}
+			}
+		}
+		self.selected = Some(name);
+		self.read_only = read_only;
+		Ok(out.into_bytes())
+	}
+
+	fn with_selected<T>(&self, f: impl FnOnce(&mut Mailbox) -> T) -> Result<T, String> {
+		let name = self.selected.as_ref().ok_or("no mailbox selected")?;
+		let mut mailboxes = self.mailboxes.lock();
+		let mailbox = mailboxes.iter_mut().find(|x| &x.name == name).ok_or("selected mailbox vanished")?;
+		Ok(f(mailbox))
+	}
+
+	fn condstore(&self) -> bool {
+		self.capabilities.iter().any(|x| x == "CONDSTORE" || x == "QRESYNC")
+	}
+
+	fn fetch(&mut self, args: &str) -> Response {
+		let (set, items) = split_word(args);
+		let items = items.to_ascii_uppercase();
+		let changed_since = items.find("(CHANGEDSINCE ")
+			.and_then(|x| items[x + 14..].trim_end_matches(')').parse::<u64>().ok());
+		let condstore = self.condstore();
+		let headers = items.contains("HEADER.FIELDS (MESSAGE-ID)");
+		let full = items.split(|c: char| !c.is_ascii_alphanumeric() && c != '.').any(|x| x == "RFC822");
+		let flags = items.contains("FLAGS") || full;
+		let read_only = self.read_only;
+		self.with_selected(|mailbox| {
+			let max = mailbox.messages.iter().map(|x| x.uid).max().unwrap_or(0);
+			let mut out = Vec::new();
+			for (i, message) in mailbox.messages.iter_mut().enumerate() {
+				if !in_set(set, message.uid, max) || changed_since.map(|x| message.modseq <= x).unwrap_or(false) {
+					continue;
+				}
+				if full && !read_only && !message.has_flag("\\Seen") {
+					message.flags.push("\\Seen".to_owned());
+				}
+				out.extend_from_slice(format!("* {} FETCH (UID {}", i + 1, message.uid).as_bytes());
+				if condstore && changed_since.is_some() {
+					out.extend_from_slice(format!(" MODSEQ ({})", message.modseq).as_bytes());
+				}
+				if flags {
+					out.extend_from_slice(format!(" FLAGS ({})", message.flags.join(" ")).as_bytes());
+				}
+				if headers {
+					let header = message.message_id_header();
+					out.extend_from_slice(format!(" BODY[HEADER.FIELDS (MESSAGE-ID)] {{{}}}\r\n", header.len()).as_bytes());
+					out.extend_from_slice(&header);
+				}
+				if full {
+					out.extend_from_slice(format!(" RFC822 {{{}}}\r\n", message.data.len()).as_bytes());
+					out.extend_from_slice(&message.data);
+				}
+				out.extend_from_slice(b")\r\n");
+			}
+			out
+		})
+	}
+
+	fn store(&mut self, args: &str) -> Response {
+		if self.read_only {
+			return Err("mailbox is read-only".to_owned());
+		}
+		let (set, args) = split_word(args);
+		let (op, flags) = split_word(args);
+		let op = op.to_ascii_uppercase();
+		let silent = op.ends_with(".SILENT");
+		let flags = flags.trim_start_matches('(').trim_end_matches(')').split_whitespace().map(ToOwned::to_owned).collect::<Vec<_>>();
+		self.with_selected(|mailbox| {
+			let max = mailbox.messages.iter().map(|x| x.uid).max().unwrap_or(0);
+			let mut out = Vec::new();
+			for (i, message) in mailbox.messages.iter_mut().enumerate() {
+				if !in_set(set, message.uid, max) {
+					continue;
+				}
+				if op.starts_with('+') {
+					for flag in &flags {
+						if !message.has_flag(flag) {
+							message.flags.push(flag.clone());
+						}
+					}
+				} else if op.starts_with('-') {
+					message.flags.retain(|x| !flags.contains(x));
+				} else {
+					message.flags = flags.clone();
+				}
+				mailbox.highest_modseq += 1;
+				message.modseq = mailbox.highest_modseq;
+				if !silent {
+					out.extend_from_slice(format!("* {} FETCH (UID {} FLAGS ({}))\r\n", i + 1, message.uid, message.flags.join(" ")).as_bytes());
+				}
+			}
+			out
+		})
+	}
+
+	fn mv(&mut self, args: &str) -> Response {
+		if self.read_only {
+			return Err("mailbox is read-only".to_owned());
+		}
+		let (set, target) = split_word(args);
+		let target = unquote(target);
+		let moved = self.with_selected(|mailbox| {
+			let max = mailbox.messages.iter().map(|x| x.uid).max().unwrap_or(0);
+			remove_messages(mailbox, |x| in_set(set, x.uid, max))
+		})?;
+		let mut mailboxes = self.mailboxes.lock();
+		let target = mailboxes.iter_mut().find(|x| x.name == target).ok_or("[TRYCREATE] no such mailbox")?;
+		let mut out = Vec::new();
+		for (seq, mut message) in moved {
+			message.uid = target.uid_next;
+			target.uid_next += 1;
+			target.highest_modseq += 1;
+			message.modseq = target.highest_modseq;
+			target.messages.push(message);
+			out.extend_from_slice(format!("* {} EXPUNGE\r\n", seq).as_bytes());
+		}
+		Ok(out)
+	}
+
+	fn search(&mut self, args: &str) -> Response {
+		if !args.eq_ignore_ascii_case("ALL") {
+			return Err(format!("unsupported search {}", args));
+		}
+		self.with_selected(|mailbox| {
+			format!("* SEARCH {}\r\n", mailbox.messages.iter().map(|x| x.uid.to_string()).collect::<Vec<_>>().join(" ")).into_bytes()
+		})
+	}
+
+	fn expunge(&mut self) -> Response {
+		if self.read_only {
+			return Err("mailbox is read-only".to_owned());
+		}
+		self.with_selected(|mailbox| {
+			let mut out = Vec::new();
+			for (seq, _) in remove_messages(mailbox, |x| x.has_flag("\\Deleted")) {
+				out.extend_from_slice(format!("* {} EXPUNGE\r\n", seq).as_bytes());
+			}
+			out
+		})
+	}
+}
+
+/// Remove matching messages, returning them with the sequence number
+/// to report in the EXPUNGE response (highest first, so the numbers stay valid).
+fn remove_messages(mailbox: &mut Mailbox, f: impl Fn(&Message) -> bool) -> Vec<(usize, Message)> {
+	let mut removed = Vec::new();
+	let mut i = mailbox.messages.len();
+	while i > 0 {
+		i -= 1;
+		if f(&mailbox.messages[i]) {
+			removed.push((i + 1, mailbox.messages.remove(i)));
+			mailbox.highest_modseq += 1;
+			mailbox.expunged.push((removed.last().unwrap().1.uid, mailbox.highest_modseq));
+		}
+	}
+	removed
+}
+
+fn split_word(x: &str) -> (&str, &str) {
+	x.split_once(' ').unwrap_or((x, ""))
+}
+
+fn unquote(x: &str) -> String {
+	let x = x.trim();
+	if x.len() >= 2 && x.starts_with('"') && x.ends_with('"') {
+		x[1..x.len()-1].replace("\\\"", "\"").replace("\\\\", "\\")
+	} else {
+		x.to_owned()
+	}
+}
+
+/// Check whether a UID is contained in a sequence set like `1:3,7,9:*`.
+fn in_set(set: &str, uid: u32, max: u32) -> bool {
+	let parse = |x: &str| if x == "*" { Some(max) } else { x.parse().ok() };
+	set.split(',').any(|range| {
+		let mut parts = range.splitn(2, ':');
+		let start = parts.next().and_then(parse);
+		let end = parts.next().map(parse).unwrap_or(start);
+		match (start, end) {
+			(Some(a), Some(b)) => (a.min(b)..=a.max(b)).contains(&uid),
+			_ => false
+		}
+	})
+}
+
+/// Create a fresh, empty directory for a test.
+pub fn test_dir(name: &str) -> PathBuf {
+	static COUNTER: AtomicUsize = AtomicUsize::new(0);
+	let dir = env::temp_dir().join(format!("inboxid-test-{}-{}-{}", process::id(), name, COUNTER.fetch_add(1, Ordering::SeqCst)));
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).expect("failed to create test directory");
+	dir
+}
+
+/// An account without server, storing its maildir and database in a fresh test directory.
+pub fn test_account(name: &str) -> Account {
+	let dir = test_dir(name);
+	Account {
+		maildir: dir.join("mail"),
+		db: dir.join("index.db"),
+		..Default::default()
+	}
+}